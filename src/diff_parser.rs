@@ -1,14 +1,334 @@
-use std::cell::RefCell;
-use std::io::{self, BufRead, BufReader, Lines, Read};
+use chrono::{DateTime, FixedOffset};
+use flate2::read::ZlibDecoder;
+use globset::GlobMatcher;
+use regex::Regex;
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
 use std::iter::Peekable;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
 
-const GIT_DIFF_PREFIX: &'static str = "diff --git ";
+const GIT_DIFF_PREFIX: &str = "diff --git ";
+/// `hg diff` starts its patches with `diff -r <rev> [-r <rev>] <path>`.
+const HG_DIFF_PREFIX: &str = "diff -r ";
 
-type PeekableLines<T> = Rc<RefCell<Peekable<Lines<BufReader<T>>>>>;
+/// `svn diff` starts its patches with `Index: <path>` and a line of `=`.
+const SVN_INDEX_PREFIX: &str = "Index: ";
 
+/// Whether `line` starts a patch, in git's or in Mercurial's format.
+fn is_diff_start(line: &str) -> bool {
+    line.starts_with(GIT_DIFF_PREFIX) || line.starts_with(HG_DIFF_PREFIX)
+}
+
+/// Whether `line` may start an `svn diff` patch. Only does if the line after
+/// it is all `=`, but nothing of another patch can start with it either.
+fn is_svn_index(line: &str) -> bool {
+    line.starts_with(SVN_INDEX_PREFIX)
+}
+
+/// The line of `=` below an `Index:` line.
+fn is_svn_separator(line: &str) -> bool {
+    let line = content(line);
+    !line.is_empty() && line.chars().all(|c| c == '=')
+}
+
+/// `line` without the `\r` a CRLF terminated input leaves behind.
+fn content(line: &str) -> &str {
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+/// Every way to split `line` in two at a run of spaces or tabs, leftmost
+/// first, leaving the run out.
+fn whitespace_splits(line: &str) -> impl Iterator<Item = (&str, &str)> {
+    let blank = [' ', '\t'];
+    line.char_indices()
+        .filter(move |&(i, c)| blank.contains(&c) && !line[..i].ends_with(blank))
+        .map(move |(i, _)| (&line[..i], line[i..].trim_start_matches(blank)))
+}
+
+/// Added, modified, renamed and copied files go by their new path, removed
+/// ones by their old path.
+fn display_name(old: Option<&str>, new: Option<&str>) -> Option<String> {
+    new.or(old).map(str::to_string)
+}
+
+/// Whether every path of a patch satisfies `is_match`: the one of an added or
+/// removed file, both the old and the new one of any other. A rename only
+/// matches if its source and destination both do.
+fn all_paths_match(old: Option<&str>, new: Option<&str>, is_match: impl Fn(&str) -> bool) -> bool {
+    (old.is_some() || new.is_some()) && old.into_iter().chain(new).all(is_match)
+}
+
+/// The last component of `path`, the file name without its directories.
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Whether `file` is `path` or, with `prefix`, lies anywhere below it. Whole
+/// components are compared, so `src` covers `src/main.rs` but not `srcs/a`.
+fn is_under(file: &str, path: &str, prefix: bool) -> bool {
+    file == path
+        || prefix
+            && file
+                .strip_prefix(path.trim_end_matches('/'))
+                .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// How the bytes of the input are turned into the text of its lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    /// Only accept UTF-8. Nothing past the first invalid line is read, and
+    /// [`DiffParser::invalid_line`] tells which one it was.
+    #[default]
+    Utf8Strict,
+    /// Replace invalid UTF-8 with U+FFFD. The patch keeps its shape, but the
+    /// replaced lines no longer match the file they came from.
+    Utf8Lossy,
+    /// Read every byte as the char of the same value, like Latin-1 does.
+    /// [`Encoding::encode`] gets the exact input back, but anything beyond
+    /// ASCII looks garbled as text, paths included.
+    Bytes,
+}
+
+impl Encoding {
+    fn decode(self, bytes: Vec<u8>) -> Option<String> {
+        match self {
+            Encoding::Utf8Strict => String::from_utf8(bytes).ok(),
+            Encoding::Utf8Lossy => Some(match String::from_utf8(bytes) {
+                Ok(text) => text,
+                Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+            }),
+            Encoding::Bytes => Some(bytes.into_iter().map(char::from).collect()),
+        }
+    }
+
+    /// The bytes `text` was read from. Only differs from its UTF-8 for
+    /// [`Encoding::Bytes`], where chars that can't have come from the input,
+    /// e.g. those of a prefix added to it, stay UTF-8.
+    pub fn encode(self, text: &str) -> Cow<'_, [u8]> {
+        match self {
+            Encoding::Bytes => {
+                let mut bytes = Vec::with_capacity(text.len());
+                for c in text.chars() {
+                    match u8::try_from(c) {
+                        Ok(b) => bytes.push(b),
+                        Err(_) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                    }
+                }
+                Cow::Owned(bytes)
+            }
+            _ => Cow::Borrowed(text.as_bytes()),
+        }
+    }
+}
+
+/// Like `BufRead::lines`, but only the `\n` is stripped: lines of a CRLF input
+/// keep their `\r` so they can be written back unchanged.
+struct RawLines<B: BufRead> {
+    inner: B,
+    encoding: Encoding,
+    /// Lines read so far.
+    count: usize,
+    /// The first line that couldn't be decoded, shared with the parser.
+    invalid: Arc<OnceLock<usize>>,
+}
+
+impl<B: BufRead> Iterator for RawLines<B> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.invalid.get().is_some() {
+            return None;
+        }
+
+        let mut line = Vec::new();
+        match self.inner.read_until(b'\n', &mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                self.count += 1;
+                if line.ends_with(b"\n") {
+                    line.pop();
+                }
+                match self.encoding.decode(line) {
+                    Some(line) => Some(Ok(line)),
+                    None => {
+                        let _ = self.invalid.set(self.count);
+                        None
+                    }
+                }
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+type PeekableLines<T> = Rc<RefCell<Peekable<RawLines<BufReader<T>>>>>;
+
+/// Reads patches off a stream, one at a time. A [`Patch`] reads its body
+/// straight from the stream too, so only the current one has it: moving on to
+/// the next patch skips whatever is left of the body, and a patch whose body
+/// wasn't read by then comes up empty for good. Use [`Patch::into_owned`] to
+/// keep a body around.
 pub struct DiffParser<T: Sized + Read> {
     lines: PeekableLines<T>,
+    invalid: Arc<OnceLock<usize>>,
+    /// How many patches have been started, shared with them so they can tell
+    /// whether they're still the current one.
+    started: Rc<Cell<usize>>,
+    /// The hunk being read, for as long as it's short of the lines its header
+    /// declares, whether its patch reads the body or the parser skips it.
+    unfinished: Rc<RefCell<Option<Hunk>>>,
+    /// The message of the `git format-patch` mail being read, if any.
+    commit: Option<Commit>,
+    /// Leave the leading `./` and `../` of paths be.
+    keep_relative: bool,
+    /// Take an input starting with a `---` line for a patch missing its
+    /// `diff --git` line.
+    lenient: bool,
+}
+
+/// Author and message of a commit, taken from the mail headers and body
+/// `git format-patch` writes before the diff.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Commit {
+    hash: Option<String>,
+    author: Option<String>,
+    date: Option<DateTime<FixedOffset>>,
+    message: String,
+}
+
+impl Commit {
+    /// Looks for the commit in the lines preceding a `diff --git`, either as
+    /// a `git format-patch` mail or as a `git log -p` entry.
+    fn parse(preamble: &[String]) -> Option<Self> {
+        // The hash follows "From " on the line starting the mail, and
+        // "commit " in a `git log`.
+        let hash = |line: &String, prefix| {
+            let hash = content(line)
+                .strip_prefix(prefix)?
+                .split_whitespace()
+                .next()?;
+            (hash.len() >= 7 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+                .then(|| hash.to_string())
+        };
+
+        if preamble
+            .iter()
+            .any(|l| l.starts_with("From: ") || l.starts_with("Subject: "))
+        {
+            let mut commit = Self::parse_mail(preamble)?;
+            commit.hash = preamble.iter().rev().find_map(|l| hash(l, "From "));
+            return Some(commit);
+        }
+
+        let start = preamble.iter().rposition(|l| l.starts_with("commit "))?;
+        let mut commit = Self::parse_log(&preamble[start + 1..])?;
+        commit.hash = hash(&preamble[start], "commit ");
+        Some(commit)
+    }
+
+    /// The headers of a `git log` entry are followed by the message, indented
+    /// by four spaces.
+    fn parse_log(entry: &[String]) -> Option<Self> {
+        let mut lines = entry.iter().map(|l| content(l));
+        let mut author = None;
+        let mut date = None;
+        for line in lines.by_ref().take_while(|l| !l.is_empty()) {
+            if let Some(a) = line.strip_prefix("Author: ") {
+                author = Some(a.trim().to_string());
+            } else if let Some(d) = line.strip_prefix("Date: ") {
+                date = Self::parse_date(d.trim());
+            }
+        }
+
+        let message = lines
+            .map_while(|l| l.strip_prefix("    ").or(l.trim().is_empty().then_some("")))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let message = message.trim_matches('\n');
+        if message.is_empty() {
+            return None;
+        }
+
+        Some(Commit {
+            hash: None,
+            author,
+            date,
+            message: message.to_string(),
+        })
+    }
+
+    /// `git log` writes dates like `Mon Jan 1 12:00:00 2024 +0100` unless
+    /// told otherwise with `--date`, of which the ISO and RFC 2822 formats are
+    /// understood too. Mails always carry RFC 2822 dates.
+    fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_str(date, "%a %b %e %H:%M:%S %Y %z")
+            .or_else(|_| DateTime::parse_from_rfc2822(date))
+            .or_else(|_| DateTime::parse_from_rfc3339(date))
+            .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"))
+            .ok()
+    }
+
+    /// Everything after the `---` line ending the body of the mail is the
+    /// diffstat, which is left out.
+    fn parse_mail(preamble: &[String]) -> Option<Self> {
+        let mut lines = preamble
+            .iter()
+            .map(|l| content(l))
+            .skip_while(|l| !(l.starts_with("From: ") || l.starts_with("Subject: ")));
+
+        let mut author = None;
+        let mut date = None;
+        let mut subject = None::<String>;
+        let mut last = None;
+        for line in lines.by_ref().take_while(|l| !l.is_empty()) {
+            // Long headers are folded onto indented continuation lines.
+            if line.starts_with([' ', '\t']) {
+                match last {
+                    Some("From") => author.get_or_insert_with(String::new),
+                    Some("Subject") => subject.get_or_insert_with(String::new),
+                    _ => continue,
+                }
+                .push_str(line.trim_end().trim_start_matches('\t'));
+                continue;
+            }
+
+            let (name, value) = line.split_once(": ").unwrap_or((line, ""));
+            last = Some(name);
+            match name {
+                "From" => author = Some(value.to_string()),
+                "Date" => date = Self::parse_date(value.trim()),
+                "Subject" => subject = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        // Drop the "[PATCH 1/3] " format-patch puts in front of the subject.
+        let subject = subject?;
+        let subject = match subject.strip_prefix('[').and_then(|s| s.split_once("] ")) {
+            Some((tag, rest)) if tag.starts_with("PATCH") => rest,
+            _ => &subject,
+        };
+
+        let body = lines.take_while(|l| *l != "---").collect::<Vec<_>>();
+        let mut message = subject.to_string();
+        let body = body.join("\n");
+        if !body.trim().is_empty() {
+            message.push_str("\n\n");
+            message.push_str(body.trim_matches('\n'));
+        }
+
+        Some(Commit {
+            hash: None,
+            author,
+            date,
+            message,
+        })
+    }
 }
 
 impl<T> DiffParser<T>
@@ -16,45 +336,232 @@ where
     T: Sized + Read,
 {
     pub fn new(handle: T) -> Self {
+        Self::with_encoding(handle, Encoding::default())
+    }
+
+    pub fn with_encoding(handle: T, encoding: Encoding) -> Self {
+        let invalid = Arc::new(OnceLock::new());
         DiffParser {
-            lines: Rc::new(RefCell::new(BufReader::new(handle).lines().peekable())),
+            lines: Rc::new(RefCell::new(
+                RawLines {
+                    inner: BufReader::new(handle),
+                    encoding,
+                    count: 0,
+                    invalid: invalid.clone(),
+                }
+                .peekable(),
+            )),
+            invalid,
+            started: Rc::new(Cell::new(0)),
+            unfinished: Rc::new(RefCell::new(None)),
+            commit: None,
+            keep_relative: false,
+            lenient: false,
         }
     }
 
-    fn next_patch(&mut self) -> Option<Patch<T>> {
+    /// Number of the line, counting from 1, that stopped a
+    /// [`Encoding::Utf8Strict`] parse for not being UTF-8. The patches before
+    /// it are complete, unless it was inside one of their bodies.
+    pub fn invalid_line(&self) -> Option<usize> {
+        self.invalid.get().copied()
+    }
+
+    /// Keeps the leading `./` or `../` of the paths in the patches, which are
+    /// otherwise dropped, for diffs meant to be applied from a subdirectory.
+    pub fn keep_relative(mut self, keep: bool) -> Self {
+        self.keep_relative = keep;
+        self
+    }
+
+    /// Reads an input whose first line, blank ones aside, is a `---` line as
+    /// a patch without its `diff --git` line, e.g. a fragment copied from a
+    /// review tool. It's otherwise skipped along with anything else before
+    /// the first `diff --git`.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// The hunk the input ended in the middle of, short of the lines its
+    /// header declares, as a download cut off would leave it. Only known once
+    /// every patch has been read.
+    pub fn truncated_hunk(&self) -> Option<Hunk> {
+        self.unfinished.borrow().clone()
+    }
+
+    /// Reads up to the next patch and its header. Whatever is skipped on the
+    /// way, the unread rest of the previous body included, goes to `skipped`
+    /// if given, and is dropped otherwise.
+    fn next_patch(&mut self, mut skipped: Option<&mut Vec<String>>) -> Option<Patch<T>> {
         let mut lines_iter = self.lines.borrow_mut();
-        // Skip to the next "diff" line.
-        let mut iter = lines_iter
-            .by_ref()
-            .filter_map(|l| l.ok())
-            .skip_while(|l| !l.starts_with(GIT_DIFF_PREFIX));
+        // A fragment at the start has no previous body to be mistaken for.
+        let mut fragment_start = self.lenient && self.started.get() == 0;
+        // Drop whatever is left of the previous patch's body without holding
+        // on to it. Body lines all start with a prefix, so a nested
+        // `+diff --git` in a patch of a patch can't pass for the next header.
+        while let Some(Ok(line)) = lines_iter
+            .next_if(|l| !fragment_start && l.as_ref().is_ok_and(|l| Self::is_body_line(l)))
+        {
+            let mut unfinished = self.unfinished.borrow_mut();
+            if let Some(hunk) = parse_hunk_header(&line) {
+                *unfinished = (!hunk.is_satisfied()).then_some(hunk);
+            } else if let Some(hunk) = unfinished.as_mut() {
+                hunk.count(&line);
+                if hunk.is_satisfied() {
+                    *unfinished = None;
+                }
+            }
+            if let Some(skipped) = skipped.as_mut() {
+                skipped.push(line);
+            }
+        }
+
+        // Skip to the next "diff" line, keeping what came before in case it's
+        // the commit the diff belongs to.
+        let mut preamble = Vec::new();
+        let mut header = loop {
+            // The fragment's header is read below, from the `---` line on.
+            if fragment_start && matches!(lines_iter.peek(), Some(Ok(l)) if l.starts_with("--- ")) {
+                break String::new();
+            }
+            match lines_iter.next() {
+                Some(Ok(line)) if is_diff_start(&line) => break line,
+                Some(Ok(line))
+                    if is_svn_index(&line)
+                        && matches!(lines_iter.peek(), Some(Ok(l)) if is_svn_separator(l)) =>
+                {
+                    break line;
+                }
+                Some(Ok(line)) => {
+                    fragment_start &= content(&line).trim().is_empty();
+                    preamble.push(line);
+                }
+                Some(Err(_)) => {}
+                None => {
+                    skipped.into_iter().for_each(|s| s.append(&mut preamble));
+                    return None;
+                }
+            }
+        };
+        let fragment = header.is_empty();
+        // A hunk cut short by the next patch is only inconsistent.
+        self.unfinished.replace(None);
+
+        // A new commit starts with its mbox "From " line, or with "commit " in
+        // `git log` output. Diffs of the same commit share its message.
+        if preamble.iter().any(|l| {
+            l.starts_with("From ") || l.starts_with("Subject: ") || l.starts_with("commit ")
+        }) {
+            self.commit = Commit::parse(&preamble);
+        }
+        if let Some(skipped) = skipped.as_mut() {
+            skipped.append(&mut preamble);
+        }
 
         // Extract header, old and new filenames.
-        let mut header = iter.next()?;
-        let mut old_filename;
-        let mut new_filename;
-        match header.strip_prefix(GIT_DIFF_PREFIX)?.split_once(" ") {
-            Some((a, b)) => {
-                old_filename = Self::filename(&a.to_string().replacen("a/", "", 1));
-                new_filename = Self::filename(&b.to_string().replacen("b/", "", 1));
+        let keep = self.keep_relative;
+        let paths = match header.strip_prefix(GIT_DIFF_PREFIX) {
+            // A fragment only has its `---` and `+++` lines to name the files,
+            // prefixed like git's, if at all.
+            _ if fragment => Some((None, None, true)),
+            Some(paths) => Self::diff_git_paths(paths).map(|(a, b, prefixed)| {
+                (Self::filename(a, keep), Self::filename(b, keep), prefixed)
+            }),
+            // svn names the file once, on its `Index:` line, and never
+            // prefixes it.
+            None if is_svn_index(&header) => {
+                let path = content(&header[SVN_INDEX_PREFIX.len()..]);
+                Some((
+                    Self::filename(path, keep),
+                    Self::filename(path, keep),
+                    false,
+                ))
             }
-            None => return None,
+            // Only the `---`/`+++` lines of an hg patch carry prefixes.
+            None => Self::hg_diff_path(&header)
+                .map(|path| (Self::filename(path, keep), Self::filename(path, keep), true)),
+        };
+        let svn = is_svn_index(&header);
+        let Some((mut old_filename, mut new_filename, prefixed)) = paths else {
+            skipped.into_iter().for_each(|s| s.push(header.clone()));
+            return None;
+        };
+        // `--no-prefix` diffs have no a/ and b/ to strip.
+        let strip = |path: &str, prefix: &str| match path.strip_prefix(prefix) {
+            Some(stripped) if prefixed => Self::filename(stripped, keep),
+            _ => Self::filename(path, keep),
         };
 
-        header += "\n";
+        if !fragment {
+            header += "\n";
+        }
+        let mut binary = false;
+        let mut binary_payload = None;
+        let mut extended_headers = Vec::new();
+        // Each file line should only come once.
+        let mut file_lines = (0, 0);
+        let mut repeated_file_line = None;
 
         while let Some(Ok(line)) = lines_iter.next_if(Self::should_break) {
-            if line.starts_with("--- ") {
-                old_filename = Self::filename(&line[4..].replacen("a/", "", 1));
-            } else if line.starts_with("+++ ") {
-                new_filename = Self::filename(&line[4..].replacen("b/", "", 1));
-            } else if let Some((a, b)) = line
+            let entry = ExtendedHeader::parse(content(&line));
+            match &entry {
+                // Binary and empty files have no `---`/`+++` lines to say so.
+                Some(ExtendedHeader::NewFile(_)) => old_filename = None,
+                Some(ExtendedHeader::DeletedFile(_)) => new_filename = None,
+                // Unlike the `diff --git` line, these name a single path each,
+                // so spaces in them are no trouble. Exact renames and copies
+                // have nothing else to go by.
+                Some(ExtendedHeader::RenameFrom(old) | ExtendedHeader::CopyFrom(old)) => {
+                    old_filename = Self::filename(old, keep)
+                }
+                Some(ExtendedHeader::RenameTo(new) | ExtendedHeader::CopyTo(new)) => {
+                    new_filename = Self::filename(new, keep)
+                }
+                _ => {}
+            }
+            extended_headers.extend(entry);
+
+            // The `Index:` line already named the file, so svn's `---` and
+            // `+++` lines only tell whether either side doesn't exist.
+            // Older versions mark an added file as revision 0.
+            if svn && line.starts_with("--- ") {
+                let text = content(&line);
+                if text.ends_with("\t(nonexistent)") || text.ends_with("\t(revision 0)") {
+                    old_filename = None;
+                }
+                file_lines.0 += 1;
+            } else if svn && line.starts_with("+++ ") {
+                if content(&line).ends_with("\t(nonexistent)") {
+                    new_filename = None;
+                }
+                file_lines.1 += 1;
+            // hg follows the path with a tab and a timestamp; git only adds
+            // the tab, to paths with spaces.
+            } else if let Some(old) = line.strip_prefix("--- ") {
+                old_filename = strip(old.split('\t').next().unwrap_or(old), "a/");
+                file_lines.0 += 1;
+            } else if let Some(new) = line.strip_prefix("+++ ") {
+                new_filename = strip(new.split('\t').next().unwrap_or(new), "b/");
+                file_lines.1 += 1;
+            } else if let Some((a, b)) = content(&line)
                 .strip_prefix("Binary files ")
                 .and_then(|s| s.strip_suffix(" differ"))
                 .and_then(|s| s.split_once(" and "))
             {
-                old_filename = Self::filename(&a.replacen("a/", "", 1));
-                new_filename = Self::filename(&b.replacen("b/", "", 1));
+                old_filename = strip(a, "a/");
+                new_filename = strip(b, "b/");
+                binary = true;
+            } else if content(&line) == "GIT binary patch" {
+                header.push_str(line.as_str());
+                header.push('\n');
+                binary = true;
+                binary_payload = Some(Self::binary_payload(&mut lines_iter));
+                break;
+            }
+
+            if (file_lines.0 > 1 || file_lines.1 > 1) && repeated_file_line.is_none() {
+                repeated_file_line = Some(content(&line).to_string());
             }
 
             header.push_str(line.as_str());
@@ -63,24 +570,129 @@ where
 
         drop(lines_iter);
 
-        Some(Patch::new(
+        self.started.set(self.started.get() + 1);
+        let mut patch = Patch::new(
             old_filename,
             new_filename,
             header,
             Rc::new(RefCell::new(self.clone())),
-        ))
+        );
+        patch.binary = binary;
+        patch.binary_payload = binary_payload;
+        patch.repeated_file_line = repeated_file_line;
+        patch.commit = self.commit.clone();
+        patch.prefixed = prefixed;
+        patch.extended_headers = extended_headers;
+
+        Some(patch)
+    }
+
+    /// Splits the two paths of a `diff --git` line and tells whether they carry
+    /// the usual `a/` and `b/` prefixes. Paths may contain spaces, so the split
+    /// that names the same file on both sides wins, as it does for anything
+    /// but renames and copies. Some tools separate the paths with a tab.
+    fn diff_git_paths(paths: &str) -> Option<(&str, &str, bool)> {
+        let paths = content(paths);
+        let same_file = |prefixed: bool| {
+            whitespace_splits(paths).find_map(|(a, b)| {
+                match (a.strip_prefix("a/"), b.strip_prefix("b/")) {
+                    (Some(a), Some(b)) if prefixed && a == b => Some((a, b)),
+                    _ if !prefixed && a == b => Some((a, b)),
+                    _ => None,
+                }
+            })
+        };
+
+        if let Some((a, b)) = same_file(true) {
+            return Some((a, b, true));
+        }
+        if let Some((a, b)) = same_file(false) {
+            return Some((a, b, false));
+        }
+
+        // Renamed or copied. The `rename`/`copy` and `---`/`+++` lines settle
+        // the names.
+        if let Some((a, b)) = paths.strip_prefix("a/").and_then(|paths| {
+            whitespace_splits(paths).find_map(|(a, b)| Some((a, b.strip_prefix("b/")?)))
+        }) {
+            return Some((a, b, true));
+        }
+        whitespace_splits(paths).next().map(|(a, b)| (a, b, false))
+    }
+
+    /// The path of a `diff -r <rev> [-r <rev>] <path>` line, which may contain
+    /// spaces.
+    fn hg_diff_path(line: &str) -> Option<&str> {
+        let mut rest = content(line).strip_prefix("diff ")?;
+        while let Some(revs) = rest.strip_prefix("-r ") {
+            rest = revs.split_once(' ')?.1;
+        }
+        Some(rest)
+    }
+
+    /// Collects the `literal`/`delta` blocks following a `GIT binary patch`
+    /// line. Each block is base85 data terminated by an empty line; git emits
+    /// the forward block and, optionally, the reverse one.
+    fn binary_payload(lines: &mut Peekable<RawLines<BufReader<T>>>) -> String {
+        let mut payload = String::new();
+
+        while let Some(Ok(line)) = lines.next_if(|l| {
+            l.as_ref()
+                .is_ok_and(|l| l.starts_with("literal ") || l.starts_with("delta "))
+        }) {
+            payload.push_str(&line);
+            payload.push('\n');
+
+            while let Some(Ok(line)) =
+                lines.next_if(|l| l.as_ref().is_ok_and(|l| !is_diff_start(l)))
+            {
+                payload.push_str(&line);
+                payload.push('\n');
+                if content(&line).is_empty() {
+                    break;
+                }
+            }
+        }
+
+        payload
+    }
+
+    /// Yields everything read, the lines between patches as well as the
+    /// patches, see [`Segments`].
+    pub fn segments(self) -> Segments<T> {
+        Segments {
+            parser: self,
+            pending: None,
+            done: false,
+        }
+    }
+
+    /// Reads every patch in full instead of streaming their bodies, see
+    /// [`OwnedPatch`].
+    pub fn into_owned_patches(self) -> impl Iterator<Item = OwnedPatch> {
+        self.map(Patch::into_owned)
+    }
+
+    /// Whether `line` can belong to a hunk: a header, a prefixed body line, a
+    /// `\ No newline` marker or an empty context line that lost its space.
+    fn is_body_line(line: &str) -> bool {
+        let line = content(line);
+        line.is_empty() || line.starts_with(['+', '-', ' ', '\\']) || line.starts_with("@@ -")
     }
 
     fn should_break(line: &Result<String, io::Error>) -> bool {
         match line {
-            Ok(l) => !(l.starts_with(GIT_DIFF_PREFIX) || l.starts_with("@@ -")),
+            Ok(l) => !(is_diff_start(l) || is_svn_index(l) || l.starts_with("@@ -")),
             _ => false,
         }
     }
 
-    fn filename(f: &String) -> Option<String> {
+    fn filename(f: &str, keep_relative: bool) -> Option<String> {
         if f != "/dev/null" {
             let p = f.trim();
+            if keep_relative {
+                return Some(p.to_string());
+            }
             let p1 = match p.strip_prefix("../") {
                 Some(path) => path,
                 None => p,
@@ -98,6 +710,37 @@ where
     }
 }
 
+impl DiffParser<Cursor<Vec<u8>>> {
+    /// Parses a patch that's already in memory.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(patch: &str) -> Self {
+        Self::new(Cursor::new(patch.as_bytes().to_vec()))
+    }
+}
+
+impl DiffParser<File> {
+    /// Parses each of `paths` in turn, pairing every patch with the path of the
+    /// file it came from. A file that can't be opened yields its error, and
+    /// the files after it are still read.
+    pub fn from_files<P: AsRef<Path>>(
+        paths: impl IntoIterator<Item = P>,
+    ) -> impl Iterator<Item = io::Result<(PathBuf, Patch<File>)>> {
+        paths.into_iter().flat_map(|path| {
+            let path = path.as_ref().to_path_buf();
+            let (parser, error) = match File::open(&path) {
+                Ok(file) => (Some(Self::new(file)), None),
+                Err(e) => (None, Some(Err(e))),
+            };
+            error.into_iter().chain(
+                parser
+                    .into_iter()
+                    .flatten()
+                    .map(move |patch| Ok((path.clone(), patch))),
+            )
+        })
+    }
+}
+
 impl<T> Clone for DiffParser<T>
 where
     T: Sized + Read,
@@ -105,6 +748,12 @@ where
     fn clone(&self) -> Self {
         Self {
             lines: self.lines.clone(),
+            invalid: self.invalid.clone(),
+            started: self.started.clone(),
+            unfinished: self.unfinished.clone(),
+            commit: self.commit.clone(),
+            keep_relative: self.keep_relative,
+            lenient: self.lenient,
         }
     }
 }
@@ -116,7 +765,147 @@ where
     type Item = Patch<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.next_patch()
+        self.next_patch(None)
+    }
+}
+
+/// A piece of the input, as [`DiffParser::segments`] yields them.
+// Only one is around at a time, boxing the patch would gain nothing.
+#[allow(clippy::large_enum_variant)]
+pub enum Segment<T: Sized + Read> {
+    /// Lines read between patches, as they were: commit messages, mail
+    /// headers and signatures, and whatever a patch left unread of its body.
+    Preamble(Vec<String>),
+    Patch(Patch<T>),
+}
+
+/// Iterates over a [`DiffParser`]'s input without losing any of it. Writing
+/// out every patch's header and binary payload as they are, and every
+/// preamble and body line followed by `\n`, gives back the input, short of
+/// a missing newline at its very end. Whatever a patch leaves unread of its
+/// body lands in the next preamble.
+pub struct Segments<T: Sized + Read> {
+    parser: DiffParser<T>,
+    /// The patch found after the preamble just yielded.
+    pending: Option<Patch<T>>,
+    done: bool,
+}
+
+impl<T> Iterator for Segments<T>
+where
+    T: Sized + Read,
+{
+    type Item = Segment<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(patch) = self.pending.take() {
+            return Some(Segment::Patch(patch));
+        }
+        if self.done {
+            return None;
+        }
+
+        let mut preamble = Vec::new();
+        match self.parser.next_patch(Some(&mut preamble)) {
+            Some(patch) => self.pending = Some(patch),
+            // Past a diff line it can't read, the parser gives up on the
+            // rest of the input, which is kept as it is.
+            None => {
+                self.done = true;
+                let mut lines = self.parser.lines.borrow_mut();
+                preamble.extend(lines.by_ref().map_while(Result::ok));
+            }
+        }
+        if preamble.is_empty() {
+            return self.next();
+        }
+        Some(Segment::Preamble(preamble))
+    }
+}
+
+/// A [`DiffParser`] that can be sent to another thread, for splitting many
+/// inputs at once, e.g. in a server. It reads every patch in full, so what
+/// it yields is an [`OwnedPatch`], which can be sent along as well.
+pub struct SendDiffParser<T: Sized + Read> {
+    // What a parser holds between patches, taken apart so that none of it is
+    // shared. It's put back together to read each patch, taking the lines.
+    lines: Option<Peekable<RawLines<BufReader<T>>>>,
+    invalid: Arc<OnceLock<usize>>,
+    started: usize,
+    unfinished: Option<Hunk>,
+    commit: Option<Commit>,
+    keep_relative: bool,
+    lenient: bool,
+}
+
+impl<T> SendDiffParser<T>
+where
+    T: Sized + Read,
+{
+    pub fn new(handle: T) -> Self {
+        Self::with_encoding(handle, Encoding::default())
+    }
+
+    pub fn with_encoding(handle: T, encoding: Encoding) -> Self {
+        Self::take_apart(DiffParser::with_encoding(handle, encoding))
+    }
+
+    /// See [`DiffParser::keep_relative`].
+    pub fn keep_relative(mut self, keep: bool) -> Self {
+        self.keep_relative = keep;
+        self
+    }
+
+    /// See [`DiffParser::lenient`].
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// See [`DiffParser::invalid_line`].
+    pub fn invalid_line(&self) -> Option<usize> {
+        self.invalid.get().copied()
+    }
+
+    /// See [`DiffParser::truncated_hunk`].
+    pub fn truncated_hunk(&self) -> Option<Hunk> {
+        self.unfinished.clone()
+    }
+
+    /// Takes the state out of `parser`, which no patch may hold on to.
+    fn take_apart(parser: DiffParser<T>) -> Self {
+        let lines = Rc::into_inner(parser.lines).expect("no patch outlives its turn");
+        SendDiffParser {
+            lines: Some(lines.into_inner()),
+            invalid: parser.invalid,
+            started: parser.started.get(),
+            unfinished: parser.unfinished.take(),
+            commit: parser.commit,
+            keep_relative: parser.keep_relative,
+            lenient: parser.lenient,
+        }
+    }
+}
+
+impl<T> Iterator for SendDiffParser<T>
+where
+    T: Sized + Read,
+{
+    type Item = OwnedPatch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut parser = DiffParser {
+            lines: Rc::new(RefCell::new(self.lines.take()?)),
+            invalid: self.invalid.clone(),
+            started: Rc::new(Cell::new(self.started)),
+            unfinished: Rc::new(RefCell::new(self.unfinished.take())),
+            commit: self.commit.take(),
+            keep_relative: self.keep_relative,
+            lenient: self.lenient,
+        };
+        let patch = parser.next_patch(None).map(Patch::into_owned);
+        *self = Self::take_apart(parser);
+        patch
     }
 }
 
@@ -124,11 +913,279 @@ pub struct Patch<T: Sized + Read> {
     old_filename: Option<String>,
     new_filename: Option<String>,
     header: String,
-    lines_left: u32,
-    p: char,
+    binary: bool,
+    binary_payload: Option<String>,
+    commit: Option<Commit>,
+    prefixed: bool,
+    extended_headers: Vec<ExtendedHeader>,
+    hunks: Vec<Hunk>,
+    malformed_hunk: Option<String>,
+    repeated_file_line: Option<String>,
+    in_hunk: bool,
+    /// Which of the parser's patches this is.
+    index: usize,
     parser: Rc<RefCell<DiffParser<T>>>,
 }
 
+/// A hunk of a patch, as announced by its `@@ -a,b +c,d @@` header, along
+/// with what its body turned out to hold. One is recorded per hunk while
+/// [`Patch::lines`] reads through the body.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    /// The header line as read, `\r` included.
+    raw: String,
+    section: String,
+    old_start: u32,
+    old_len: u32,
+    new_start: u32,
+    new_len: u32,
+    /// Old and new side lines actually found before the hunk ended.
+    seen: (u32, u32),
+    /// More `+`/`-`/context lines followed after the declared counts were met.
+    trailing: bool,
+}
+
+/// Parses a hunk header, `@@ -56,7 +56,8 @@ fn main() {`, into a [`Hunk`]
+/// with nothing of its body seen yet. `None` if `line` isn't one. A trailing
+/// `\r` is kept in [`Hunk::raw`] only.
+///
+/// A range without a length, like `-56`, is one line long. Some tools
+/// glue the section heading to the new range or leave out the closing
+/// `@@` entirely, so the range ends wherever its digits do.
+pub fn parse_hunk_header(line: &str) -> Option<Hunk> {
+    let (a, b) = content(line).strip_prefix("@@ -")?.split_once("+")?;
+    let end = b
+        .find(|c: char| !(c.is_ascii_digit() || c == ','))
+        .unwrap_or(b.len());
+    let (b, rest) = b.split_at(end);
+    let section = rest.trim_start().strip_prefix("@@").unwrap_or_default();
+    let range = |r: &str| -> Option<(u32, u32)> {
+        match r.trim().split_once(",") {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((r.trim().parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_len) = range(a)?;
+    let (new_start, new_len) = range(b)?;
+
+    Some(Hunk {
+        raw: line.to_string(),
+        section: section.to_string(),
+        old_start,
+        old_len,
+        new_start,
+        new_len,
+        seen: (0, 0),
+        trailing: false,
+    })
+}
+
+impl Hunk {
+    /// The `@@ -a,b +c,d @@` line, verbatim but for the `\r` of a CRLF input.
+    pub fn header(&self) -> &str {
+        content(&self.raw)
+    }
+
+    /// The header line exactly as it was read, to write it back unchanged.
+    /// Rebuilding it from the ranges may not give the same line: lengths of
+    /// one can be left out, and some tools space it differently.
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// Whatever follows the closing `@@`, usually the enclosing function as
+    /// ` fn main() {`. Empty if there's nothing, or no closing `@@`.
+    pub fn section(&self) -> &str {
+        &self.section
+    }
+
+    /// The function or section [`Self::section`] names, without the space
+    /// git puts before it, if there is one: `@@ fn main() {` and `@@fn
+    /// main() {` both name `fn main() {`. Any further indentation is the
+    /// heading's own. `None` if it names nothing.
+    pub fn context_heading(&self) -> Option<&str> {
+        let heading = self.section.strip_prefix(' ').unwrap_or(&self.section);
+        Some(heading.trim_end()).filter(|heading| !heading.trim_start().is_empty())
+    }
+
+    /// First line of the old side the hunk covers. 0 if the old side is empty,
+    /// e.g. for added files.
+    pub fn old_start(&self) -> u32 {
+        self.old_start
+    }
+
+    /// Number of old side lines the header declares.
+    pub fn old_len(&self) -> u32 {
+        self.old_len
+    }
+
+    /// First line of the new side the hunk covers. 0 if the new side is empty,
+    /// e.g. for removed files.
+    pub fn new_start(&self) -> u32 {
+        self.new_start
+    }
+
+    /// Number of new side lines the header declares.
+    pub fn new_len(&self) -> u32 {
+        self.new_len
+    }
+
+    /// Whether the hunk starts at the first line of the old file. Where it
+    /// ends can't be told from the patch alone, since the length of the file
+    /// isn't part of it.
+    pub fn is_at_file_start(&self) -> bool {
+        self.old_start <= 1
+    }
+
+    /// Old and new side lines found in the body, which only match
+    /// [`Self::old_len`] and [`Self::new_len`] if the patch is well formed.
+    pub fn body_len(&self) -> (u32, u32) {
+        self.seen
+    }
+
+    /// Whether more body lines followed once the declared counts were met.
+    pub fn has_trailing_lines(&self) -> bool {
+        self.trailing
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.seen == (self.old_len, self.new_len) && !self.trailing
+    }
+
+    fn is_satisfied(&self) -> bool {
+        self.seen.0 >= self.old_len && self.seen.1 >= self.new_len
+    }
+
+    /// Counts body `line` on the sides it belongs to. `\ No newline` markers
+    /// belong to neither.
+    fn count(&mut self, line: &str) {
+        // An empty line is a context line whose leading space got trimmed.
+        match content(line).chars().next() {
+            Some(' ') | None => {
+                self.seen.0 += 1;
+                self.seen.1 += 1;
+            }
+            Some('-') => self.seen.0 += 1,
+            Some('+') => self.seen.1 += 1,
+            _ => {}
+        }
+    }
+}
+
+/// A line of git's extended header, between `diff --git` and the body.
+/// Paths are kept as they were written, quotes included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExtendedHeader {
+    OldMode(String),
+    NewMode(String),
+    /// `new file mode`, with the mode of the added file.
+    NewFile(String),
+    /// `deleted file mode`, with the mode of the removed file.
+    DeletedFile(String),
+    RenameFrom(String),
+    RenameTo(String),
+    CopyFrom(String),
+    CopyTo(String),
+    /// Percentage of the file left unchanged by a rename or copy.
+    SimilarityIndex(u8),
+    /// Percentage of a rewritten file that changed.
+    DissimilarityIndex(u8),
+    /// `index <old>..<new> [<mode>]`, with abbreviated object names.
+    Index {
+        old: String,
+        new: String,
+        mode: Option<String>,
+    },
+    /// Either `Binary files ... differ` or `GIT binary patch`.
+    Binary,
+}
+
+impl ExtendedHeader {
+    const KEYS: [&str; 11] = [
+        "old mode ",
+        "new mode ",
+        "new file mode ",
+        "deleted file mode ",
+        "rename from ",
+        "rename to ",
+        "copy from ",
+        "copy to ",
+        "similarity index ",
+        "dissimilarity index ",
+        "index ",
+    ];
+
+    fn parse(line: &str) -> Option<Self> {
+        if line.starts_with("Binary files ") || line == "GIT binary patch" {
+            return Some(ExtendedHeader::Binary);
+        }
+        let (key, value) = Self::KEYS
+            .iter()
+            .find_map(|key| Some((*key, line.strip_prefix(key)?)))?;
+        let percent = |s: &str| s.strip_suffix('%')?.parse().ok();
+
+        Some(match key {
+            "old mode " => ExtendedHeader::OldMode(value.to_string()),
+            "new mode " => ExtendedHeader::NewMode(value.to_string()),
+            "new file mode " => ExtendedHeader::NewFile(value.to_string()),
+            "deleted file mode " => ExtendedHeader::DeletedFile(value.to_string()),
+            "rename from " => ExtendedHeader::RenameFrom(value.to_string()),
+            "rename to " => ExtendedHeader::RenameTo(value.to_string()),
+            "copy from " => ExtendedHeader::CopyFrom(value.to_string()),
+            "copy to " => ExtendedHeader::CopyTo(value.to_string()),
+            "similarity index " => ExtendedHeader::SimilarityIndex(percent(value)?),
+            "dissimilarity index " => ExtendedHeader::DissimilarityIndex(percent(value)?),
+            _ => {
+                let (range, mode) = match value.split_once(' ') {
+                    Some((range, mode)) => (range, Some(mode.to_string())),
+                    None => (value, None),
+                };
+                let (old, new) = range.split_once("..")?;
+                ExtendedHeader::Index {
+                    old: old.to_string(),
+                    new: new.to_string(),
+                    mode,
+                }
+            }
+        })
+    }
+
+    fn copy(headers: &[ExtendedHeader]) -> Option<(String, String)> {
+        let from = headers.iter().find_map(|h| match h {
+            ExtendedHeader::CopyFrom(from) => Some(from),
+            _ => None,
+        })?;
+        let to = headers.iter().find_map(|h| match h {
+            ExtendedHeader::CopyTo(to) => Some(to),
+            _ => None,
+        })?;
+        Some((from.clone(), to.clone()))
+    }
+
+    /// Any change to the contents or the path comes with more header lines,
+    /// at least an `index` one.
+    fn is_mode_only(headers: &[ExtendedHeader]) -> bool {
+        !headers.is_empty()
+            && headers
+                .iter()
+                .all(|h| matches!(h, ExtendedHeader::OldMode(_) | ExtendedHeader::NewMode(_)))
+    }
+
+    /// git stores submodules as commits, with the `160000` mode.
+    fn is_submodule(headers: &[ExtendedHeader]) -> bool {
+        headers.iter().any(|h| match h {
+            ExtendedHeader::OldMode(mode)
+            | ExtendedHeader::NewMode(mode)
+            | ExtendedHeader::NewFile(mode)
+            | ExtendedHeader::DeletedFile(mode)
+            | ExtendedHeader::Index {
+                mode: Some(mode), ..
+            } => mode == "160000",
+            _ => false,
+        })
+    }
+}
+
 impl<T> Patch<T>
 where
     T: Sized + Read,
@@ -139,56 +1196,758 @@ where
         header: String,
         parser: Rc<RefCell<DiffParser<T>>>,
     ) -> Self {
+        let index = parser.borrow().started.get();
         Patch {
             old_filename,
             new_filename,
             header,
-            lines_left: 0,
-            p: ' ',
+            binary: false,
+            binary_payload: None,
+            commit: None,
+            prefixed: true,
+            extended_headers: Vec::new(),
+            hunks: Vec::new(),
+            malformed_hunk: None,
+            repeated_file_line: None,
+            in_hunk: false,
+            index,
             parser,
         }
     }
 
+    /// Path of the file before the change, `None` if it was added. Prefer
+    /// this over [`Self::old_filename`].
+    pub fn old_path(&self) -> Option<&str> {
+        self.old_filename.as_deref()
+    }
+
+    /// Path of the file after the change, `None` if it was removed. Prefer
+    /// this over [`Self::new_filename`].
+    pub fn new_path(&self) -> Option<&str> {
+        self.new_filename.as_deref()
+    }
+
+    /// The path spatch names the patch after: the new path, or the old one
+    /// for removed files. `None` only if both sides are `/dev/null`.
+    pub fn display_name(&self) -> Option<String> {
+        display_name(self.old_path(), self.new_path())
+    }
+
+    pub fn old_filename(&self) -> &Option<String> {
+        &self.old_filename
+    }
+
+    pub fn new_filename(&self) -> &Option<String> {
+        &self.new_filename
+    }
+
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    /// Whether the header announced a binary diff, either as
+    /// `Binary files ... differ` or as a `GIT binary patch`.
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    /// The still encoded `literal`/`delta` blocks of a `GIT binary patch`,
+    /// exactly as they appeared in the input. `None` for every other kind of
+    /// patch, including `Binary files ... differ` ones.
+    pub fn binary_payload(&self) -> Option<&str> {
+        self.binary_payload.as_deref()
+    }
+
+    /// The contents of the file a `GIT binary patch` carries in full: the new
+    /// file, or the old one for removed files. `None` for `Binary files ...
+    /// differ` patches, which carry nothing, and for changes only written as
+    /// a delta.
+    pub fn decode_binary(&self) -> Option<Vec<u8>> {
+        decode_binary_payload(self.binary_payload()?, self.new_path().is_none())
+    }
+
+    /// Whether the paths in the header carry git's `a/` and `b/` prefixes,
+    /// which `--no-prefix` diffs don't.
+    pub fn has_prefixes(&self) -> bool {
+        self.prefixed
+    }
+
+    /// Paths, header and kind of change, copied out of the patch.
+    pub fn meta(&self) -> PatchMeta {
+        PatchMeta::new(
+            self.old_path(),
+            self.new_path(),
+            &self.header,
+            self.binary,
+            self.copy().is_some(),
+        )
+    }
+
+    /// The recognized lines of the header after `diff --git`, in order.
+    pub fn extended_headers(&self) -> &[ExtendedHeader] {
+        &self.extended_headers
+    }
+
+    /// Source and destination of a file copied in a `git diff -C` patch. The
+    /// destination is a new file, with the body holding its changes relative
+    /// to the source.
+    pub fn copy(&self) -> Option<(String, String)> {
+        ExtendedHeader::copy(&self.extended_headers)
+    }
+
+    /// Whether the patch only changes the file's mode, e.g. after a `chmod +x`.
+    /// Such patches are just `old mode`/`new mode` lines, without a body.
+    pub fn is_mode_only_change(&self) -> bool {
+        ExtendedHeader::is_mode_only(&self.extended_headers)
+    }
+
+    /// Whether the patch adds, removes or moves a submodule. Its body is then
+    /// a `Subproject commit <hash>` line per side rather than file contents,
+    /// and the paths name the submodule's directory.
+    pub fn is_submodule(&self) -> bool {
+        ExtendedHeader::is_submodule(&self.extended_headers)
+    }
+
+    /// Whether the paths of the patch match `glob`, old and new alike: a
+    /// rename out of the matched files doesn't. The whole path is matched,
+    /// so unless `glob` was built with a literal separator, `*.rs` matches
+    /// `src/main.rs` too.
+    pub fn matches_glob(&self, glob: &GlobMatcher) -> bool {
+        all_paths_match(self.old_path(), self.new_path(), |p| glob.is_match(p))
+    }
+
+    /// Like [`Patch::matches_glob`], matching only the file names, without
+    /// their directories.
+    pub fn matches_basename_glob(&self, glob: &GlobMatcher) -> bool {
+        all_paths_match(self.old_path(), self.new_path(), |p| {
+            glob.is_match(basename(p))
+        })
+    }
+
+    /// Like [`Patch::matches_glob`], for a regex found anywhere in the paths.
+    pub fn matches_regex(&self, regex: &Regex) -> bool {
+        all_paths_match(self.old_path(), self.new_path(), |p| regex.is_match(p))
+    }
+
+    /// Whether the file at `path` is changed, on either side of the patch.
+    /// Renames and copies affect both their source and destination.
+    pub fn affects(&self, path: &str) -> bool {
+        [self.old_path(), self.new_path()]
+            .into_iter()
+            .flatten()
+            .any(|file| is_under(file, path, false))
+    }
+
+    /// Like [`Patch::affects`], but also true for any file below the
+    /// directory `path`.
+    pub fn affects_prefix(&self, path: &str) -> bool {
+        [self.old_path(), self.new_path()]
+            .into_iter()
+            .flatten()
+            .any(|file| is_under(file, path, true))
+    }
+
+    /// The `From:` of the `git format-patch` mail the diff came in.
+    pub fn author(&self) -> Option<&str> {
+        self.commit.as_ref()?.author.as_deref()
+    }
+
+    /// Subject and body of the `git format-patch` mail the diff came in,
+    /// without the `[PATCH]` tag and the diffstat. `None` for plain diffs.
+    pub fn commit_message(&self) -> Option<&str> {
+        self.commit.as_ref().map(|c| c.message.as_str())
+    }
+
+    /// Hash of the commit the diff belongs to, as the `From <hash>` line of a
+    /// `git format-patch` mail or the `commit` line of a `git log -p` gives it.
+    pub fn commit_hash(&self) -> Option<&str> {
+        self.commit.as_ref()?.hash.as_deref()
+    }
+
+    /// When the commit the diff belongs to was authored, from the `Date:`
+    /// line of a `git log -p` entry or of a `git format-patch` mail.
+    pub fn commit_date(&self) -> Option<DateTime<FixedOffset>> {
+        self.commit.as_ref()?.date
+    }
+
+    /// Every hunk read so far. Only complete once [`Patch::lines`] has been
+    /// drained.
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    /// The `@@ -` line that couldn't be parsed as a hunk header, if
+    /// [`Patch::lines`] stopped at one. Whatever followed it was left out.
+    pub fn malformed_hunk(&self) -> Option<&str> {
+        self.malformed_hunk.as_deref()
+    }
+
+    /// The second `--- ` or `+++ ` line of the header, if there is one: the
+    /// mark of a corrupt diff. The last of them names the file.
+    pub fn repeated_file_line(&self) -> Option<&str> {
+        self.repeated_file_line.as_deref()
+    }
+
+    /// The body, hunk headers included, read off the input as it's iterated.
+    /// Comes up empty once the parser has moved on to the next patch.
+    pub fn lines(&mut self) -> PatchLines<'_, T> {
+        PatchLines { patch: self }
+    }
+
+    /// Old side lines covered by all hunks together, as their headers declare.
+    /// Reads the rest of the body to find every hunk, so [`Patch::lines`]
+    /// comes up empty afterwards.
+    pub fn old_len(&mut self) -> u32 {
+        self.lines().for_each(drop);
+        self.hunks
+            .iter()
+            .map(Hunk::old_len)
+            .fold(0, u32::saturating_add)
+    }
+
+    /// New side lines covered by all hunks together, as their headers declare.
+    /// Reads the rest of the body like [`Patch::old_len`].
+    pub fn new_len(&mut self) -> u32 {
+        self.lines().for_each(drop);
+        self.hunks
+            .iter()
+            .map(Hunk::new_len)
+            .fold(0, u32::saturating_add)
+    }
+
+    /// Reads the rest of the body into memory, detaching the patch from the
+    /// parser.
+    pub fn into_owned(mut self) -> OwnedPatch {
+        let lines = self.lines().collect();
+        // Nothing is read past a line that isn't UTF-8, so the patch it came
+        // up in ends there, unless its last hunk was already complete.
+        let cut_short = self.parser.borrow().invalid_line().is_some()
+            && self.hunks.last().is_none_or(|hunk| !hunk.is_satisfied());
+        OwnedPatch {
+            old_filename: self.old_filename,
+            new_filename: self.new_filename,
+            header: self.header,
+            binary: self.binary,
+            binary_payload: self.binary_payload,
+            commit: self.commit,
+            extended_headers: self.extended_headers,
+            lines,
+            hunks: self.hunks,
+            malformed_hunk: self.malformed_hunk,
+            repeated_file_line: self.repeated_file_line,
+            prefixed: self.prefixed,
+            cut_short,
+            sides: Default::default(),
+        }
+    }
+}
+
+/// What a patch says about the file it changes, without the body. Holds no
+/// part of the parser, so it can be kept, or sent to another thread, while
+/// the parser moves on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatchMeta {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub header: String,
+    pub binary: bool,
+    /// The file didn't exist before, as for additions and copies.
+    pub new_file: bool,
+    /// The file is gone after the change.
+    pub deleted_file: bool,
+}
+
+impl PatchMeta {
+    fn new(old: Option<&str>, new: Option<&str>, header: &str, binary: bool, copied: bool) -> Self {
+        PatchMeta {
+            old_path: old.map(str::to_string),
+            new_path: new.map(str::to_string),
+            header: header.to_string(),
+            binary,
+            new_file: old.is_none() || copied,
+            deleted_file: new.is_none(),
+        }
+    }
+
+    pub fn display_name(&self) -> Option<String> {
+        display_name(self.old_path.as_deref(), self.new_path.as_deref())
+    }
+}
+
+/// A [`Patch`] whose body has already been read. Costs the memory of holding
+/// the whole body, but it can be inspected before being written out and no
+/// longer has to be consumed before the parser moves on.
+#[derive(Clone, Debug)]
+pub struct OwnedPatch {
+    old_filename: Option<String>,
+    new_filename: Option<String>,
+    header: String,
+    binary: bool,
+    binary_payload: Option<String>,
+    commit: Option<Commit>,
+    extended_headers: Vec<ExtendedHeader>,
+    lines: Vec<String>,
+    hunks: Vec<Hunk>,
+    malformed_hunk: Option<String>,
+    repeated_file_line: Option<String>,
+    prefixed: bool,
+    cut_short: bool,
+    /// Line numbers of the old and the new file, each with the index in
+    /// `lines` of the body line holding it. Built by [`Self::line_at`] the
+    /// first time it's asked about a side.
+    sides: [OnceCell<Vec<(u32, usize)>>; 2],
+}
+
+/// One of the two files a patch is between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    /// The file before the change: context and removed lines.
+    Old,
+    /// The file after the change: context and added lines.
+    New,
+}
+
+impl OwnedPatch {
+    /// Path of the file before the change, `None` if it was added. Prefer
+    /// this over [`Self::old_filename`].
+    pub fn old_path(&self) -> Option<&str> {
+        self.old_filename.as_deref()
+    }
+
+    /// Path of the file after the change, `None` if it was removed. Prefer
+    /// this over [`Self::new_filename`].
+    pub fn new_path(&self) -> Option<&str> {
+        self.new_filename.as_deref()
+    }
+
+    /// The path the patch is named after, like [`Patch::display_name`].
+    pub fn display_name(&self) -> Option<String> {
+        display_name(self.old_path(), self.new_path())
+    }
+
+    /// Path of the file before the change, as an `Option` to borrow.
     pub fn old_filename(&self) -> &Option<String> {
         &self.old_filename
     }
 
+    /// Path of the file after the change, as an `Option` to borrow.
     pub fn new_filename(&self) -> &Option<String> {
         &self.new_filename
     }
 
+    /// The header, from `diff --git` up to the first hunk.
     pub fn header(&self) -> &str {
         &self.header
     }
 
-    /// @@ -56,7 +56,8 @@ ...........
-    ///       |^|   |^| that's what we want
-    fn parse_hunk_start(line: &String) -> Option<(u32, u32)> {
-        let (mut a, mut b) = line.strip_prefix("@@ -")?.split_once("+")?;
-        a = a.trim();
-        b = b.trim().split_once(" @@")?.0;
+    /// Whether the header announced a binary diff, like [`Patch::is_binary`].
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    /// Paths, header and kind of change, copied out of the patch.
+    pub fn meta(&self) -> PatchMeta {
+        PatchMeta::new(
+            self.old_path(),
+            self.new_path(),
+            &self.header,
+            self.binary,
+            self.copy().is_some(),
+        )
+    }
 
-        Some((
-            match a.split_once(",") {
-                Some((_, suff)) => suff,
-                None => a,
+    /// The still encoded blocks of a `GIT binary patch`, like [`Patch::binary_payload`].
+    pub fn binary_payload(&self) -> Option<&str> {
+        self.binary_payload.as_deref()
+    }
+
+    /// The file a `GIT binary patch` carries in full, like [`Patch::decode_binary`].
+    pub fn decode_binary(&self) -> Option<Vec<u8>> {
+        decode_binary_payload(self.binary_payload()?, self.new_path().is_none())
+    }
+
+    /// Whether the paths in the header carry git's `a/` and `b/` prefixes.
+    pub fn has_prefixes(&self) -> bool {
+        self.prefixed
+    }
+
+    /// The recognized lines of the header after `diff --git`, in order.
+    pub fn extended_headers(&self) -> &[ExtendedHeader] {
+        &self.extended_headers
+    }
+
+    /// Source and destination of a copied file, like [`Patch::copy`].
+    pub fn copy(&self) -> Option<(String, String)> {
+        ExtendedHeader::copy(&self.extended_headers)
+    }
+
+    /// Whether the patch only changes the file's mode, without a body.
+    pub fn is_mode_only_change(&self) -> bool {
+        ExtendedHeader::is_mode_only(&self.extended_headers)
+    }
+
+    /// Whether the patch adds, removes or moves a submodule, like [`Patch::is_submodule`].
+    pub fn is_submodule(&self) -> bool {
+        ExtendedHeader::is_submodule(&self.extended_headers)
+    }
+
+    /// Whether the paths of the patch match `glob`, like [`Patch::matches_glob`].
+    pub fn matches_glob(&self, glob: &GlobMatcher) -> bool {
+        all_paths_match(self.old_path(), self.new_path(), |p| glob.is_match(p))
+    }
+
+    /// Like [`Self::matches_glob`], matching only the file names.
+    pub fn matches_basename_glob(&self, glob: &GlobMatcher) -> bool {
+        all_paths_match(self.old_path(), self.new_path(), |p| {
+            glob.is_match(basename(p))
+        })
+    }
+
+    /// Like [`Self::matches_glob`], for a regex found anywhere in the paths.
+    pub fn matches_regex(&self, regex: &Regex) -> bool {
+        all_paths_match(self.old_path(), self.new_path(), |p| regex.is_match(p))
+    }
+
+    /// Whether the file at `path` is changed, on either side of the patch.
+    pub fn affects(&self, path: &str) -> bool {
+        [self.old_path(), self.new_path()]
+            .into_iter()
+            .flatten()
+            .any(|file| is_under(file, path, false))
+    }
+
+    /// Like [`Self::affects`], but also true for any file below `path`.
+    pub fn affects_prefix(&self, path: &str) -> bool {
+        [self.old_path(), self.new_path()]
+            .into_iter()
+            .flatten()
+            .any(|file| is_under(file, path, true))
+    }
+
+    /// The `From:` of the `git format-patch` mail the diff came in.
+    pub fn author(&self) -> Option<&str> {
+        self.commit.as_ref()?.author.as_deref()
+    }
+
+    /// Subject and body of the mail the diff came in, like [`Patch::commit_message`].
+    pub fn commit_message(&self) -> Option<&str> {
+        self.commit.as_ref().map(|c| c.message.as_str())
+    }
+
+    /// Hash of the commit the diff belongs to, like [`Patch::commit_hash`].
+    pub fn commit_hash(&self) -> Option<&str> {
+        self.commit.as_ref()?.hash.as_deref()
+    }
+
+    /// When the commit the diff belongs to was authored.
+    pub fn commit_date(&self) -> Option<DateTime<FixedOffset>> {
+        self.commit.as_ref()?.date
+    }
+
+    /// The body, hunk headers included, as [`Patch::lines`] yielded it.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Every hunk of the body.
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    /// The `@@ -` line that couldn't be parsed as a hunk header, if any.
+    pub fn malformed_hunk(&self) -> Option<&str> {
+        self.malformed_hunk.as_deref()
+    }
+
+    /// The second `--- ` or `+++ ` line of the header, if there is one.
+    pub fn repeated_file_line(&self) -> Option<&str> {
+        self.repeated_file_line.as_deref()
+    }
+
+    /// Whether the patch ends early at the line that stopped an
+    /// [`Encoding::Utf8Strict`] parse, see [`DiffParser::invalid_line`].
+    pub fn is_cut_short(&self) -> bool {
+        self.cut_short
+    }
+
+    /// Number of `@@ -` hunks in the body.
+    pub fn hunk_count(&self) -> usize {
+        self.hunks.len()
+    }
+
+    /// Old side lines covered by all hunks together, as their headers declare.
+    pub fn old_len(&self) -> u32 {
+        self.hunks
+            .iter()
+            .map(Hunk::old_len)
+            .fold(0, u32::saturating_add)
+    }
+
+    /// New side lines covered by all hunks together, as their headers declare.
+    pub fn new_len(&self) -> u32 {
+        self.hunks
+            .iter()
+            .map(Hunk::new_len)
+            .fold(0, u32::saturating_add)
+    }
+
+    /// Whether the patch has hunks and all of them only change whitespace.
+    /// See [`Self::drop_whitespace_only_hunks`].
+    pub fn is_whitespace_only(&self) -> bool {
+        self.hunk_ranges().is_some_and(|ranges| {
+            ranges
+                .iter()
+                .all(|r| whitespace_only(&self.lines[r.clone()]))
+        })
+    }
+
+    /// Removes the hunks whose changes are only whitespace: their removed
+    /// lines, in order, only differ from their added lines in leading and
+    /// trailing whitespace. Such hunks add as many lines as they remove, so
+    /// the hunks after them still apply. Returns how many were removed.
+    pub fn drop_whitespace_only_hunks(&mut self) -> usize {
+        let Some(ranges) = self.hunk_ranges() else {
+            return 0;
+        };
+        let mut lines = self.lines[..ranges[0].start - 1].to_vec();
+        let mut hunks = Vec::with_capacity(self.hunks.len());
+        for (range, hunk) in ranges.into_iter().zip(&self.hunks) {
+            if !whitespace_only(&self.lines[range.clone()]) {
+                lines.extend_from_slice(&self.lines[range.start - 1..range.end]);
+                hunks.push(hunk.clone());
             }
-            .parse::<u32>()
-            .ok()?,
-            match b.split_once(",") {
-                Some((_, suff)) => suff,
-                None => b,
+        }
+
+        let dropped = self.hunks.len() - hunks.len();
+        self.lines = lines;
+        self.hunks = hunks;
+        self.sides = Default::default();
+        dropped
+    }
+
+    /// Line `n`, counting from 1, of the old or the new file, if the body
+    /// shows it: a context line, or one the patch removes or adds. Without
+    /// its `-`, `+` or space prefix and line ending.
+    pub fn line_at(&self, side: Side, n: u32) -> Option<&str> {
+        let numbers = self.sides[side as usize].get_or_init(|| self.side_lines(side));
+        let i = numbers
+            .binary_search_by_key(&n, |&(number, _)| number)
+            .ok()?;
+        let line = content(&self.lines[numbers[i].1]);
+        Some(line.get(1..).unwrap_or_default())
+    }
+
+    /// The line numbers of `side` the body shows, in order, each with the
+    /// index in [`Self::lines`] of the line holding it.
+    fn side_lines(&self, side: Side) -> Vec<(u32, usize)> {
+        let ours = match side {
+            Side::Old => '-',
+            Side::New => '+',
+        };
+        let mut numbers = Vec::new();
+        let mut next = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            if let Some(hunk) = parse_hunk_header(line) {
+                next = Some(match side {
+                    Side::Old => hunk.old_start,
+                    Side::New => hunk.new_start,
+                });
+                continue;
             }
-            .parse::<u32>()
-            .ok()?,
-        ))
+            let Some(number) = next.as_mut() else {
+                continue;
+            };
+            // An empty line is a context line whose leading space got trimmed.
+            let kind = content(line).chars().next().unwrap_or(' ');
+            if kind == ' ' || kind == ours {
+                numbers.push((*number, i));
+                *number += 1;
+            }
+        }
+        numbers.sort_by_key(|&(number, _)| number);
+        numbers
     }
 
-    pub fn lines<'a>(&mut self) -> PatchLines<'_, T> {
-        PatchLines { patch: self }
+    /// A rename from the file `removed` deletes to the one `added` adds, if
+    /// both patches come from the same commit, if any, and the file has the
+    /// same contents on both: what some tools write for a file moved without
+    /// a change. The rename has no body, like git's exact renames, and keeps
+    /// `removed`'s commit. The object names of the `index` lines decide, if
+    /// both have one, and the bodies otherwise. `None` for anything else.
+    pub fn merge_rename(removed: &OwnedPatch, added: &OwnedPatch) -> Option<OwnedPatch> {
+        let (Some(old), None) = (removed.old_path(), removed.new_path()) else {
+            return None;
+        };
+        let (None, Some(new)) = (added.old_path(), added.new_path()) else {
+            return None;
+        };
+        if removed.commit != added.commit || removed.prefixed != added.prefixed {
+            return None;
+        }
+        // The object names of the `index` lines tell right away, even for
+        // empty files.
+        let blob = |patch: &OwnedPatch, old: bool| {
+            patch.extended_headers.iter().find_map(|h| match h {
+                ExtendedHeader::Index { old: name, .. } if old => Some(name.clone()),
+                ExtendedHeader::Index { new: name, .. } if !old => Some(name.clone()),
+                _ => None,
+            })
+        };
+        let same = match (removed.binary, added.binary) {
+            _ if let (Some(a), Some(b)) = (blob(removed, true), blob(added, false)) => {
+                a.starts_with(&b) || b.starts_with(&a)
+            }
+            (false, false) => {
+                let contents = side_contents(&removed.lines, '-');
+                !contents.is_empty() && contents == side_contents(&added.lines, '+')
+            }
+            (true, true) => removed
+                .decode_binary()
+                .is_some_and(|c| !c.is_empty() && Some(&c) == added.decode_binary().as_ref()),
+            _ => false,
+        };
+        if !same {
+            return None;
+        }
+
+        let mode = |patch: &OwnedPatch| {
+            patch.extended_headers.iter().find_map(|h| match h {
+                ExtendedHeader::NewFile(mode) | ExtendedHeader::DeletedFile(mode) => {
+                    Some(mode.clone())
+                }
+                _ => None,
+            })
+        };
+        let (a, b) = if removed.prefixed {
+            ("a/", "b/")
+        } else {
+            ("", "")
+        };
+        let mut header = format!("{}{}{} {}{}\n", GIT_DIFF_PREFIX, a, old, b, new);
+        if let (Some(old_mode), Some(new_mode)) = (mode(removed), mode(added))
+            && old_mode != new_mode
+        {
+            header += &format!("old mode {}\nnew mode {}\n", old_mode, new_mode);
+        }
+        header += &format!(
+            "similarity index 100%\nrename from {}\nrename to {}\n",
+            old, new
+        );
+        let extended_headers = header
+            .lines()
+            .skip(1)
+            .filter_map(ExtendedHeader::parse)
+            .collect();
+
+        Some(OwnedPatch {
+            old_filename: Some(old.to_string()),
+            new_filename: Some(new.to_string()),
+            header,
+            binary: false,
+            binary_payload: None,
+            commit: removed.commit.clone(),
+            extended_headers,
+            lines: Vec::new(),
+            hunks: Vec::new(),
+            malformed_hunk: None,
+            repeated_file_line: None,
+            prefixed: removed.prefixed,
+            cut_short: removed.cut_short || added.cut_short,
+            sides: Default::default(),
+        })
+    }
+
+    /// Where the body of every hunk lies in [`Self::lines`], just past its
+    /// header line. `None` without hunks, or if the lines don't match them.
+    fn hunk_ranges(&self) -> Option<Vec<Range<usize>>> {
+        let starts: Vec<_> = (0..self.lines.len())
+            .filter(|&i| self.lines[i].starts_with("@@ -"))
+            .collect();
+        if starts.is_empty() || starts.len() != self.hunks.len() {
+            return None;
+        }
+        let ends = starts.iter().skip(1).copied().chain([self.lines.len()]);
+        Some(
+            starts
+                .iter()
+                .zip(ends)
+                .map(|(&start, end)| start + 1..end)
+                .collect(),
+        )
     }
 }
 
+/// Whether the changes in a hunk's `body` only touch whitespace at either end
+/// of their lines. A hunk without changes doesn't count.
+fn whitespace_only(body: &[String]) -> bool {
+    let side = |prefix: char| {
+        body.iter()
+            .filter_map(move |line| line.strip_prefix(prefix))
+            .map(str::trim)
+    };
+    body.iter().any(|line| line.starts_with(['-', '+'])) && side('-').eq(side('+'))
+}
+
+/// Git's base85 alphabet, in the order of the values it encodes.
+const BASE85: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Decodes the contents a `GIT binary patch` payload carries for the new
+/// file, or with `old` for the old one, which git only writes as the second
+/// block. `None` if that block is a `delta` against the other side, which
+/// can't be undone without it, or isn't valid.
+pub fn decode_binary_payload(payload: &str, old: bool) -> Option<Vec<u8>> {
+    let mut blocks = payload.split_terminator('\n').map(content);
+    if old {
+        blocks.by_ref().find(|line| line.is_empty())?;
+    }
+    let size: usize = blocks.next()?.strip_prefix("literal ")?.parse().ok()?;
+
+    let mut deflated = Vec::new();
+    for line in blocks.take_while(|line| !line.is_empty()) {
+        // Each line starts with how many bytes it decodes to, A-Z for 1 to
+        // 26 and a-z for 27 to 52, followed by groups of 5 chars for 4 bytes.
+        let (len, data) = line.split_at_checked(1)?;
+        let len = match len.as_bytes()[0] {
+            c @ b'A'..=b'Z' => (c - b'A') as usize + 1,
+            c @ b'a'..=b'z' => (c - b'a') as usize + 27,
+            _ => return None,
+        };
+        let mut bytes = Vec::with_capacity(data.len() / 5 * 4);
+        for group in data.as_bytes().chunks(5) {
+            let mut value: u32 = 0;
+            for c in group {
+                let digit = BASE85.iter().position(|b| b == c)? as u32;
+                value = value.checked_mul(85)?.checked_add(digit)?;
+            }
+            bytes.extend(value.to_be_bytes());
+        }
+        if bytes.len() < len {
+            return None;
+        }
+        deflated.extend(&bytes[..len]);
+    }
+
+    let mut contents = Vec::with_capacity(size);
+    ZlibDecoder::new(deflated.as_slice())
+        .read_to_end(&mut contents)
+        .ok()?;
+    (contents.len() == size).then_some(contents)
+}
+
+/// The file contents a body that only adds or only removes carries: its lines
+/// without their `prefix`. Hunk headers are left out and `\ No newline`
+/// markers kept, as is anything else.
+fn side_contents(body: &[String], prefix: char) -> Vec<&str> {
+    body.iter()
+        .filter(|line| !line.starts_with("@@ -"))
+        .map(|line| match line.strip_prefix(prefix) {
+            Some(text) => text,
+            None => line,
+        })
+        .collect()
+}
+
 pub struct PatchLines<'a, T: Sized + Read> {
     patch: &'a mut Patch<T>,
 }
@@ -200,36 +1959,66 @@ where
     type Item = String;
     fn next(&mut self) -> Option<Self::Item> {
         let parser = self.patch.parser.borrow();
+        // The rest of the body was skipped when the parser moved on.
+        if parser.started.get() != self.patch.index {
+            return None;
+        }
         let mut lines_iter = parser.lines.borrow_mut();
-        if self.patch.lines_left == 0 {
-            let line = match lines_iter.peek() {
-                Some(Ok(line)) => line,
-                _ => return None,
-            };
-            if let Some((a, b)) = Patch::<T>::parse_hunk_start(line) {
-                if a > b {
-                    self.patch.lines_left = a;
-                    self.patch.p = '-';
-                } else {
-                    self.patch.lines_left = b;
-                    self.patch.p = '+';
-                }
-                return Some(lines_iter.next().unwrap().unwrap()); // Consume the hunk header.
-            } else {
-                return None;
-            }
-        }
-        if let Some(line) = lines_iter.next() {
-            let line = match line {
-                Ok(line) => line,
-                Err(_) => return None,
-            };
-            if line.starts_with(self.patch.p) || line.starts_with(' ') {
-                self.patch.lines_left -= 1;
+        let line = match lines_iter.peek() {
+            Some(Ok(line)) => line,
+            _ => return None,
+        };
+
+        // Don't let a hunk with inflated counts run into the next hunk or file.
+        if is_diff_start(line) || is_svn_index(line) || line.starts_with("@@ -") {
+            self.patch.in_hunk = false;
+        }
+
+        if self.patch.in_hunk {
+            let line = lines_iter.next()?.ok()?;
+            let hunk = self.patch.hunks.last_mut()?;
+            hunk.count(&line);
+            self.patch.in_hunk = !hunk.is_satisfied();
+            let mut unfinished = parser.unfinished.borrow_mut();
+            match unfinished.as_mut() {
+                Some(open) if self.patch.in_hunk => open.seen = hunk.seen,
+                _ => *unfinished = None,
             }
-            Some(line)
-        } else {
-            None
+            return Some(line);
+        }
+
+        // "\ No newline at end of file" trails the hunk's last line.
+        if line.starts_with('\\') {
+            return lines_iter.next()?.ok();
         }
+
+        if let Some(hunk) = parse_hunk_header(line) {
+            self.patch.in_hunk = !hunk.is_satisfied();
+            parser
+                .unfinished
+                .replace(self.patch.in_hunk.then(|| hunk.clone()));
+            self.patch.hunks.push(hunk);
+            return lines_iter.next()?.ok(); // Consume the hunk header.
+        }
+
+        // Nothing after a header that can't be parsed can be told apart from
+        // the garbage between patches, so the body ends here.
+        if line.starts_with("@@ -") {
+            self.patch.malformed_hunk = Some(content(line).to_string());
+            return None;
+        }
+
+        // Body lines after a finished hunk mean its counts were too small.
+        // "-- " is the signature separator `git format-patch` ends with.
+        let line = content(line);
+        if let Some(hunk) = self.patch.hunks.last_mut()
+            && line.starts_with(['+', '-', ' '])
+            && line != "--"
+            && line != "-- "
+        {
+            hunk.trailing = true;
+        }
+
+        None
     }
 }
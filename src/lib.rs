@@ -1 +1,4 @@
+pub mod color;
 pub mod diff_parser;
+pub mod splitter;
+pub mod stats;
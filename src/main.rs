@@ -1,25 +1,78 @@
-use anyhow;
+use chrono::{DateTime, FixedOffset, NaiveDate};
 use clap::{self, Parser};
-use spatch::diff_parser::{DiffParser, Patch};
+use indicatif::{ProgressBar, ProgressStyle};
+use spatch::color::{ColorChoice, Painter};
+use spatch::diff_parser::Encoding;
+use spatch::splitter::{
+    self, Archive, ContentFilter, ContentSide, ExtractMode, FileProcessing, FilterType,
+    HeaderTemplate, LineEnding, ManifestEntry, NameFrom, OnCollision, SortKey, SplitCount,
+    SplitOptions,
+};
 use std::{
+    collections::{HashMap, HashSet},
     fs::File,
-    io::{self, Read, Write},
-    path::PathBuf,
+    io::{self, IsTerminal, Read, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
 
-#[derive(Clone, Debug)]
-enum FileProcessing {
-    ExtractPatch,
-    ExtractFile,
+/// Prints spatch's own log records to stderr, leaving stdout to the patches.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target().starts_with("spatch") && metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            log::Level::Error => eprintln!("error: {}", record.args()),
+            log::Level::Warn => eprintln!("warning: {}", record.args()),
+            _ => eprintln!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Reports how much of the input was read so far to a progress bar.
+struct ProgressReader<R: Read> {
+    inner: R,
+    bar: ProgressBar,
 }
 
-#[derive(Clone, Debug)]
-enum FilterType {
-    Regex(regex::Regex),
-    Glob(globset::Glob),
-    OnlyNew(FileProcessing),
-    OnlyRemoved(FileProcessing),
-    None,
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bar.inc(n as u64);
+        Ok(n)
+    }
+}
+
+/// How much of the input is kept to tell what it was when no patch came of it.
+const HEAD_LEN: u64 = 64 * 1024;
+
+/// Keeps a copy of the first [`HEAD_LEN`] bytes read.
+struct HeadReader<R: Read> {
+    inner: R,
+    head: Vec<u8>,
+}
+
+impl<R: Read> Read for HeadReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let room = (HEAD_LEN as usize).saturating_sub(self.head.len());
+        self.head.extend_from_slice(&buf[..n.min(room)]);
+        Ok(n)
+    }
 }
 
 #[derive(Clone, Debug, clap::Args)]
@@ -36,10 +89,73 @@ struct AddedRemovedGroup {
     only_removed: bool,
 }
 
+#[derive(Clone, Debug, clap::Subcommand)]
+enum Command {
+    /// Split patches into one file per diffed file, also done without a subcommand
+    Split(Box<SplitArgs>),
+    /// Join split patches back into a single diff
+    Combine {
+        #[arg(
+            help = "Directory to recursively read the .patch and .diff files from, in name order"
+        )]
+        dir: PathBuf,
+
+        #[arg(long, short, help = "File to write the diff to instead of stdout")]
+        output: Option<PathBuf>,
+    },
+    /// Print how many lines the patches add and remove, like git diff --stat
+    Stats(Inputs),
+    /// Check that hunk line counts match their bodies, without writing anything
+    Verify(Inputs),
+}
+
+#[derive(Clone, Debug, clap::Args)]
+struct Inputs {
+    #[arg(help = "Patch files to read. Reads from stdin if not specified")]
+    files: Vec<PathBuf>,
+}
+
 #[derive(Clone, Debug, Parser)]
 #[command(author, version, about, long_about = None)]
-struct Args {
-    #[arg(long, short, help = "Output directory for split patches")]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    split: SplitArgs,
+
+    #[arg(long, short, global = true, help = "Only print warnings and errors")]
+    #[arg(default_value_t = false)]
+    #[arg(conflicts_with = "verbose")]
+    quiet: bool,
+
+    #[arg(
+        long,
+        short,
+        global = true,
+        help = "Also print the path of every written file"
+    )]
+    #[arg(default_value_t = false)]
+    verbose: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "WHEN",
+        help = "Color the output of stats and --list. auto only does on a terminal, unless NO_COLOR is set"
+    )]
+    #[arg(value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+}
+
+#[derive(Clone, Debug, clap::Args)]
+struct SplitArgs {
+    #[arg(
+        long,
+        short,
+        help = "Output directory for split patches, made along with its parents, with a warning, if missing"
+    )]
     output_dir: Option<PathBuf>,
 
     #[clap(flatten)]
@@ -60,169 +176,1050 @@ struct Args {
     #[arg(value_parser = regex::Regex::new)]
     regex: Option<regex::Regex>,
 
-    #[arg(long, help = "Filter patches by filename glob pattern")]
+    #[arg(
+        long,
+        help = "Filter patches by filename glob pattern, matched against the whole path. * matches / too, so *.rs picks src/main.rs"
+    )]
     #[arg(conflicts_with = "regex")]
     #[arg(group = "filter")]
     #[arg(value_parser = globset::Glob::new)]
     glob: Option<globset::Glob>,
 
+    #[arg(
+        long,
+        help = "Match --glob against the file name only, leaving out its directories"
+    )]
+    #[arg(default_value_t = false)]
+    #[arg(requires = "glob")]
+    glob_basename: bool,
+
+    #[arg(long, help = "Match --glob and --regex regardless of case")]
+    #[arg(default_value_t = false)]
+    ignore_case: bool,
+
+    #[arg(
+        long,
+        help = "Only keep patches with an added or removed line matching this regex"
+    )]
+    #[arg(value_parser = regex::Regex::new)]
+    content_regex: Option<regex::Regex>,
+
+    #[arg(long, help = "Which lines --content-regex is matched against")]
+    #[arg(value_enum, default_value_t = ContentSide::Both)]
+    #[arg(requires = "content_regex")]
+    content_side: ContentSide,
+
+    #[arg(
+        long,
+        value_name = "DATE",
+        help = "Only keep patches of commits made on or after DATE, as YYYY-MM-DD or an RFC 3339 time. For git log -p and git format-patch input"
+    )]
+    #[arg(value_parser = parse_since)]
+    since: Option<DateTime<FixedOffset>>,
+
+    #[arg(
+        long,
+        value_name = "DATE",
+        help = "Only keep patches of commits made on or before DATE, as YYYY-MM-DD or an RFC 3339 time"
+    )]
+    #[arg(value_parser = parse_until)]
+    until: Option<DateTime<FixedOffset>>,
+
+    #[arg(
+        long,
+        help = "Leave out hunks that only change whitespace at either end of their lines, and patches made only of such hunks"
+    )]
+    #[arg(default_value_t = false, conflicts_with = "extract_file")]
+    drop_whitespace_only: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Leave out the first N patches of each input that pass the filters"
+    )]
+    #[arg(default_value_t = 0)]
+    skip: usize,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Stop after N patches of each input, counted after --skip"
+    )]
+    max_patches: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Write N consecutive patches into each file, named chunk-0001.patch and so on"
+    )]
+    #[arg(conflicts_with = "extract_file")]
+    chunk_size: Option<NonZeroUsize>,
+
     #[arg(long, help = "Patch files to split. Reads from stdin if not specified")]
-    #[arg(num_args = 1.., value_delimiter=' ')]
+    #[arg(num_args = 0.., value_delimiter=' ')]
     files: Vec<PathBuf>,
+
+    #[arg(
+        value_name = "FILES",
+        help = "More patch files to split, taken as they are even if they start with -"
+    )]
+    #[arg(last = true)]
+    trailing_files: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Split every patch file listed in FILE, one path per line. - reads the list from stdin"
+    )]
+    files_from: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Like --files-from, with the paths separated by NUL bytes, e.g. by find -print0"
+    )]
+    #[arg(conflicts_with = "files_from")]
+    files_from0: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Warn about files listed by --files-from that don't exist and leave them out, instead of failing"
+    )]
+    #[arg(default_value_t = false)]
+    skip_missing: bool,
+
+    #[arg(
+        long,
+        help = "Recursively split every .patch and .diff file found under this directory"
+    )]
+    input_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Follow symlinks found under --input-dir, entering every directory once"
+    )]
+    #[arg(default_value_t = false, requires = "input_dir")]
+    follow_symlinks: bool,
+
+    #[arg(long, help = "Extension of the generated patch files, empty for none")]
+    #[arg(default_value = "patch")]
+    extension: String,
+
+    #[arg(
+        long,
+        help = "Put this before the name of every generated patch, e.g. hotfix- for hotfix-src-main.rs.patch"
+    )]
+    #[arg(
+        default_value = "",
+        hide_default_value = true,
+        allow_hyphen_values = true
+    )]
+    #[arg(conflicts_with = "extract_file")]
+    output_prefix: String,
+
+    #[arg(
+        long,
+        help = "Put this after the name of every generated patch, before the extension"
+    )]
+    #[arg(
+        default_value = "",
+        hide_default_value = true,
+        allow_hyphen_values = true
+    )]
+    #[arg(conflicts_with = "extract_file")]
+    output_suffix: String,
+
+    #[arg(long, help = "Write one patch per hunk, named with a #N suffix")]
+    #[arg(default_value_t = false)]
+    #[arg(conflicts_with = "extract_file")]
+    split_by_hunk: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Strip N leading components from the paths in the patches, like patch -pN"
+    )]
+    #[arg(default_value_t = 0)]
+    strip: usize,
+
+    #[arg(
+        long,
+        help = "Keep the leading ./ or ../ of the paths in the patches, for diffs applied from a subdirectory"
+    )]
+    #[arg(default_value_t = false)]
+    keep_relative: bool,
+
+    #[arg(
+        long,
+        help = "Also split a fragment the input starts with, one whose first line is --- instead of diff --git, as copied from a review tool"
+    )]
+    #[arg(default_value_t = false)]
+    lenient: bool,
+
+    #[arg(
+        long,
+        help = "Prefix of the old paths in the written headers, instead of a/"
+    )]
+    #[arg(conflicts_with = "no_prefix")]
+    src_prefix: Option<String>,
+
+    #[arg(
+        long,
+        help = "Prefix of the new paths in the written headers, instead of b/"
+    )]
+    #[arg(conflicts_with = "no_prefix")]
+    dst_prefix: Option<String>,
+
+    #[arg(long, help = "Write the paths in the headers without any prefix")]
+    #[arg(default_value_t = false)]
+    no_prefix: bool,
+
+    #[arg(
+        long,
+        help = "Recreate the directory structure of the diffed files instead of joining path components with -"
+    )]
+    #[arg(default_value_t = false)]
+    preserve_dirs: bool,
+
+    #[arg(
+        long,
+        help = "Write patches into a directory named after the top-level directory of the diffed file, flattening the rest of the path"
+    )]
+    #[arg(default_value_t = false, conflicts_with = "preserve_dirs")]
+    group_by_dir: bool,
+
+    #[arg(
+        long,
+        help = "Which path names the patch of a renamed, copied, added or removed file. Files missing the one asked for go by the other"
+    )]
+    #[arg(value_enum, default_value_t = NameFrom::Auto)]
+    name_from: NameFrom,
+
+    #[arg(
+        long,
+        help = "What to do when patches get the same name, within an input or across them: fail, number them with ~N or keep the last one"
+    )]
+    #[arg(value_enum, default_value_t = OnCollision::Number)]
+    on_collision: OnCollision,
+
+    #[arg(
+        long,
+        help = "How to read the input: stop at the first line that isn't UTF-8, replace invalid UTF-8, or keep the raw bytes"
+    )]
+    #[arg(value_enum, default_value_t = Encoding::Utf8Strict)]
+    encoding: Encoding,
+
+    #[arg(long, help = "Line endings of the generated files")]
+    #[arg(value_enum, default_value_t = LineEnding::Auto)]
+    line_ending: LineEnding,
+
+    #[arg(
+        long,
+        help = "Trim trailing spaces and tabs off added lines, like git apply --whitespace=fix"
+    )]
+    #[arg(default_value_t = false)]
+    normalize_whitespace: bool,
+
+    #[arg(
+        long,
+        short = 'R',
+        help = "Write every patch the other way around, undoing its changes like git diff -R"
+    )]
+    #[arg(default_value_t = false, conflicts_with = "extract_file")]
+    reverse: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Cut the context around the changes of every hunk down to N lines, like diff -UN"
+    )]
+    #[arg(conflicts_with = "extract_file")]
+    context: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Check that hunk line counts match their bodies. Only writes patches if -o, --tar or --tar-gz is given too"
+    )]
+    #[arg(default_value_t = false)]
+    verify: bool,
+
+    #[arg(
+        long,
+        help = "Fail on inputs that end in the middle of a hunk, as a cut off download does, that have more than one --- or +++ line in a header, or with a line that isn't UTF-8 under --encoding utf8-strict"
+    )]
+    #[arg(default_value_t = false)]
+    strict: bool,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Check that every patch applies to the files in DIR with git apply --check, failing if any doesn't. Only writes patches if -o, --tar or --tar-gz is given too"
+    )]
+    #[arg(conflicts_with = "extract_file")]
+    worktree: Option<PathBuf>,
+
+    #[arg(long, help = "Write the patches into this tar archive instead of -o")]
+    #[arg(conflicts_with_all = ["output_dir", "tar_gz"])]
+    tar: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Write the patches into this gzipped tar archive instead of -o"
+    )]
+    #[arg(conflicts_with = "output_dir")]
+    tar_gz: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Write a tab separated list of every diffed file, the file it was written to and how it changed"
+    )]
+    manifest: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write the SHA-256 of every file written to FILE, in the format sha256sum -c checks"
+    )]
+    checksums: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "KEY",
+        help = "Write the patches of each input in this order, biggest first for the counts. Holds all of an input's patches in memory"
+    )]
+    sort: Option<SortKey>,
+
+    #[arg(
+        long,
+        help = "Write a removed file and an added file with the same contents as one rename, for diffs made without rename detection. Holds all of an input's patches in memory"
+    )]
+    #[arg(default_value_t = false)]
+    merge_renames: bool,
+
+    #[arg(
+        long,
+        help = "Number the patches of each input in the order they are written, 0001- for the first, like git format-patch"
+    )]
+    #[arg(default_value_t = false, conflicts_with_all = ["extract_file", "chunk_size"])]
+    numbered: bool,
+
+    #[arg(
+        long,
+        help = "Wrap every patch in a mail for git am, numbered like --numbered. Patches without their commit get a subject naming the file and your git identity as author"
+    )]
+    #[arg(default_value_t = false)]
+    #[arg(conflicts_with_all = ["extract_file", "chunk_size", "include_commit_headers"])]
+    mailbox: bool,
+
+    #[arg(
+        long,
+        help = "Skip patches byte-equal to one written before, header included, e.g. the same change picked onto several branches. Compared after --normalize-whitespace"
+    )]
+    #[arg(default_value_t = false)]
+    dedup: bool,
+
+    #[arg(
+        long,
+        help = "Show a progress bar on stderr, if it's a terminal. Counts patches for stdin"
+    )]
+    #[arg(default_value_t = false)]
+    progress: bool,
+
+    #[arg(
+        long,
+        help = "Write the commit each patch of a git log -p or git format-patch input came from above it"
+    )]
+    #[arg(default_value_t = false, conflicts_with = "extract_file")]
+    include_commit_headers: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write the contents of FILE instead of the header of every patch, filling in {original_header}, {filename}, {additions} and {deletions}"
+    )]
+    #[arg(conflicts_with = "extract_file")]
+    template_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Print A, M, D, R or C and the path of every patch passing the filters instead of writing them"
+    )]
+    #[arg(default_value_t = false)]
+    #[arg(conflicts_with_all = ["output_dir", "extract_file", "tar", "tar_gz", "manifest", "checksums", "verify"])]
+    list: bool,
+
+    #[arg(long, help = "Fail if no patch passes the filters")]
+    #[arg(default_value_t = false)]
+    fail_on_empty: bool,
+
+    #[arg(
+        long,
+        short = 'j',
+        help = "Number of input files to split in parallel. With --dedup, every file is read once beforehand too"
+    )]
+    #[arg(default_value = "1")]
+    jobs: NonZeroUsize,
 }
 
-fn should_skip_patch<T: Sized + Read>(patch: &Patch<T>, filter: &FilterType) -> bool {
-    match filter {
-        FilterType::None => false,
-        FilterType::Glob(glob) => {
-            let matcher = glob.compile_matcher();
-            match (patch.old_filename(), patch.old_filename()) {
-                (Some(a), Some(b)) => !(matcher.is_match(a) && matcher.is_match(b)),
-                (Some(a), None) => !matcher.is_match(a),
-                (None, Some(b)) => !matcher.is_match(b),
-                (None, None) => unreachable!(),
-            }
+fn split_file(
+    path: &Path,
+    options: &SplitOptions,
+    manifest: &mut Vec<ManifestEntry>,
+) -> anyhow::Result<SplitCount> {
+    if !path.is_file() {
+        return Err(anyhow::anyhow!("{} is not a file", path.display()));
+    }
+
+    let mut file = HeadReader {
+        inner: File::open(path)?,
+        head: Vec::new(),
+    };
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let count = match &options.progress {
+        Some(bar) => {
+            let reader = ProgressReader {
+                inner: &mut file,
+                bar: bar.clone(),
+            };
+            splitter::split(reader, options, &stem, manifest)?
         }
-        FilterType::Regex(expr) => match (patch.old_filename(), patch.old_filename()) {
-            (Some(a), Some(b)) => !(expr.is_match(a) && expr.is_match(b)),
-            (Some(a), None) => !expr.is_match(a),
-            (None, Some(b)) => !expr.is_match(b),
-            (None, None) => unreachable!(),
-        },
-        FilterType::OnlyNew(_) => patch.old_filename().is_none(),
-        FilterType::OnlyRemoved(_) => patch.new_filename().is_none(),
-    }
-}
-
-fn split_patch<T: Sized + Read>(
-    handle: T,
-    filter: &FilterType,
-    patchfile: &String,
-    output_dir: &PathBuf,
-) -> anyhow::Result<()> {
-    let parser = DiffParser::new(handle);
-
-    parser
-        .filter_map(|p| {
-            if should_skip_patch(&p, filter) {
-                return None;
+        None => splitter::split(&mut file, options, &stem, manifest)?,
+    };
+    // Nothing to split, so nothing to say either.
+    if count.seen == 0 {
+        log::debug!("{} has no patches", path.display());
+        refuse_stat_output(&file.head)?;
+    } else {
+        log::info!("Split {} into {} patches", path.display(), count.kept);
+    }
+    Ok(count)
+}
+
+/// A file line of `git diff --stat` output: ` path | N ++--`, or `Bin` and
+/// the sizes for binary files.
+static STAT_FILE_LINE: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"^ .+ \| +(\d+( [+-]*)?|Bin\b.*)$").expect("valid regex"));
+
+/// The summary line ending `git diff --stat` output.
+static STAT_SUMMARY: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"^ \d+ files? changed").expect("valid regex"));
+
+/// Fails if `head`, the start of an input without any patches, looks like
+/// `git diff --stat` output: ` path | N ++--` lines and a summary, but no diff.
+/// An easy mistake that would otherwise pass for a diff with nothing in it.
+fn refuse_stat_output(head: &[u8]) -> anyhow::Result<()> {
+    let text = String::from_utf8_lossy(head);
+    if text.lines().any(|l| STAT_FILE_LINE.is_match(l))
+        && text.lines().any(|l| STAT_SUMMARY.is_match(l))
+    {
+        return Err(anyhow::anyhow!(
+            "input looks like --stat output, not a full diff. Leave out --stat to get the patches"
+        ));
+    }
+    Ok(())
+}
+
+/// Runs `task` for every index below `n` on up to `jobs` threads, the calling
+/// one among them, and returns what it returned in index order.
+fn in_parallel<R: Send>(n: usize, jobs: usize, task: impl Fn(usize) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(n));
+
+    let worker = || {
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            if i >= n {
+                break;
             }
+            let result = task(i);
+            results.lock().unwrap().push((i, result));
+        }
+    };
 
-            let f = match filter {
-                FilterType::OnlyRemoved(FileProcessing::ExtractFile) => PathBuf::from(
-                    p.old_filename().as_ref()
-                        .expect("(extremely invalid patch) cannot extract removed file because old filename was /dev/null"),
-                ),
-                FilterType::OnlyNew(FileProcessing::ExtractFile) => PathBuf::from(
-                    p.new_filename().as_ref()
-                        .expect("(extremely invalid patch) cannot extract added file because new filename was /dev/null"),
-                ),
-                _ => {
-                    let new_name = match (p.old_filename(), p.new_filename()) {
-                        (_, Some(b)) => b,
-                        (Some(a), _) => a,
-                        _ => unreachable!("(extremely invalid patch) cannot have both old and new filenames /dev/null")
-                    }.replace("/", "-");
-
-                    PathBuf::from(if patchfile.is_empty() {
-                        new_name
-                    } else {
-                        format!("{}+{}", new_name, patchfile)
-                    })
-                    .with_added_extension("patch")
-                }
-            };
+    std::thread::scope(|s| {
+        for _ in 1..jobs.min(n) {
+            s.spawn(worker);
+        }
+        worker();
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// For every one of `files`, the digests of its patches that a file before it
+/// has too, see [`splitter::digests`]. Reads all of the files through.
+fn earlier_digests(
+    files: &[PathBuf],
+    jobs: usize,
+    options: &SplitOptions,
+) -> Vec<HashSet<[u8; 32]>> {
+    // Inputs that can't be read fail their split later on.
+    let digests = in_parallel(files.len(), jobs, |i| match File::open(&files[i]) {
+        Ok(file) if files[i].is_file() => splitter::digests(file, options),
+        _ => HashSet::new(),
+    });
 
-            Some((output_dir.join(f), p))
+    let mut seen = HashSet::new();
+    digests
+        .into_iter()
+        .map(|digests| {
+            let earlier = digests.intersection(&seen).copied().collect();
+            seen.extend(digests);
+            earlier
         })
-        .try_for_each(|(f, mut patch)| {
-            let dirname = f.parent().ok_or(anyhow::anyhow!(
-                "could not find parent of '{}'",
-                f.display()
-            ))?;
-            if !dirname.exists() {
-                std::fs::create_dir_all(dirname)?;
+        .collect()
+}
+
+/// The indices of `files` split one after another, in input order, so that
+/// patches getting the same name are numbered or refused the same way on any
+/// number of threads. Names end in `+` and the input's stem, so only inputs
+/// whose stems end alike can share one; extracted files are named after
+/// their path alone, so then all inputs can.
+fn collision_groups(files: &[PathBuf], options: &SplitOptions) -> Vec<Vec<usize>> {
+    let extract = matches!(
+        options.filter,
+        FilterType::OnlyNew(FileProcessing::ExtractFile(_))
+            | FilterType::OnlyRemoved(FileProcessing::ExtractFile(_))
+    );
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut by_key: HashMap<String, usize> = HashMap::new();
+    for (i, path) in files.iter().enumerate() {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let key = match extract {
+            true => String::new(),
+            false => stem.rsplit('+').next().unwrap_or_default().to_string(),
+        };
+        match by_key.get(&key) {
+            Some(&group) => groups[group].push(i),
+            None => {
+                by_key.insert(key, groups.len());
+                groups.push(vec![i]);
             }
+        }
+    }
+    groups
+}
 
-            let mut file_patch = File::create(f)?;
+/// Splits each input file on up to `jobs` threads. Every file gets its own
+/// parser, so the only thing shared between workers is the queue position.
+/// A failing file doesn't stop the others; all failures are reported at the end.
+/// Manifest entries are added in input order, whichever thread wrote them, and
+/// with `--dedup` a patch several files share is written for the first of
+/// them, as it is on one thread. Inputs whose patches could get the same
+/// name are split on the same thread, see [`collision_groups`]. Returns how
+/// many patches were split off all files.
+fn split_files(
+    files: &[PathBuf],
+    jobs: usize,
+    options: &SplitOptions,
+    manifest: &mut Vec<ManifestEntry>,
+) -> anyhow::Result<SplitCount> {
+    // One set of digests shared as the inputs are split would go to whichever
+    // thread gets to a patch first. Reading the inputs beforehand tells each
+    // one what the inputs before it have.
+    let earlier = match &options.dedup {
+        Some(_) if jobs > 1 && files.len() > 1 => Some(earlier_digests(files, jobs, options)),
+        _ => None,
+    };
 
-            match filter {
-                FilterType::OnlyNew(FileProcessing::ExtractFile) => {}
-                _ => file_patch.write_all(patch.header().as_bytes())?,
-            };
+    let split = |i: usize| {
+        let mut written = Vec::new();
+        let own;
+        let options = match &earlier {
+            Some(earlier) => {
+                own = SplitOptions {
+                    dedup: Some(Arc::new(Mutex::new(earlier[i].clone()))),
+                    ..options.clone()
+                };
+                &own
+            }
+            None => options,
+        };
+        let count = split_file(&files[i], options, &mut written);
+        (count, written)
+    };
+    let groups = match jobs {
+        1 => vec![(0..files.len()).collect()],
+        _ => collision_groups(files, options),
+    };
+    let mut results: Vec<_> = in_parallel(groups.len(), jobs, |g| {
+        groups[g].iter().map(|&i| (i, split(i))).collect::<Vec<_>>()
+    })
+    .into_iter()
+    .flatten()
+    .collect();
+    results.sort_by_key(|(i, _)| *i);
+    let results = results.into_iter().map(|(_, result)| result);
 
-            patch
-                .lines()
-                .filter_map(|line| match filter {
-                    FilterType::OnlyNew(FileProcessing::ExtractFile) => {
-                        if line.starts_with("@@ -") {
-                            None
-                        } else {
-                            Some(line[1..].to_string())
-                        }
-                    }
-                    _ => Some(line),
-                })
-                .try_for_each(|line| -> anyhow::Result<()> {
-                    file_patch
-                        .write_all(format!("{}\n", line).as_bytes())
-                        .map_err(|e| anyhow::Error::from(e))
-                })
-        })
+    let mut count = SplitCount::default();
+    let mut failures = Vec::new();
+    for (path, (result, written)) in files.iter().zip(results) {
+        manifest.extend(written);
+        match result {
+            Ok(split) => {
+                count.seen += split.seen;
+                count.kept += split.kept;
+            }
+            Err(e) => failures.push((path, e)),
+        }
+    }
+    if failures.is_empty() {
+        return Ok(count);
+    }
+
+    failures.sort_by_key(|(path, _)| *path);
+    for (path, e) in &failures {
+        log::error!("Failed to split {}: {:#}", path.display(), e);
+    }
+
+    Err(anyhow::anyhow!(
+        "{} of {} input files could not be split",
+        failures.len(),
+        files.len()
+    ))
+}
+
+/// Parses the date of `--since` or `--until`: an RFC 3339 time, or a day,
+/// which counts from its first second if `end_of_day` isn't set and up to its
+/// last one if it is. Days are taken in UTC.
+fn parse_date(date: &str, end_of_day: bool) -> Result<DateTime<FixedOffset>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(date) {
+        return Ok(time);
+    }
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| format!("'{}' is neither YYYY-MM-DD nor an RFC 3339 time", date))?;
+    let time = match end_of_day {
+        true => day.and_hms_opt(23, 59, 59),
+        false => day.and_hms_opt(0, 0, 0),
+    };
+    Ok(time.unwrap_or_default().and_utc().fixed_offset())
+}
+
+fn parse_since(date: &str) -> Result<DateTime<FixedOffset>, String> {
+    parse_date(date, false)
+}
+
+fn parse_until(date: &str) -> Result<DateTime<FixedOffset>, String> {
+    parse_date(date, true)
+}
+
+/// Reads the paths listed in `list`, or in stdin for `-`, separated by
+/// `separator`. Empty entries are ignored, as are the `\r` of CRLF lists.
+/// Listed files must exist unless `skip_missing` is set, in which case the
+/// missing ones are left out with a warning.
+fn read_file_list(
+    list: &Path,
+    separator: char,
+    skip_missing: bool,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let text = if list == Path::new("-") {
+        io::read_to_string(io::stdin())?
+    } else {
+        std::fs::read_to_string(list)
+            .map_err(|e| anyhow::anyhow!("Couldn't read {}: {}", list.display(), e))?
+    };
+
+    let mut files = Vec::new();
+    for (i, entry) in text.split(separator).enumerate() {
+        let entry = entry.strip_suffix('\r').unwrap_or(entry);
+        if entry.is_empty() {
+            continue;
+        }
+        let path = PathBuf::from(entry);
+        if path.exists() {
+            files.push(path);
+        } else if skip_missing {
+            log::warn!("{}: skipping {}, it doesn't exist", list.display(), entry);
+        } else {
+            return Err(anyhow::anyhow!(
+                "{}: entry {} names {}, which doesn't exist",
+                list.display(),
+                i + 1,
+                entry
+            ));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Reads and checks the `--template-file`.
+fn read_template(path: &Path) -> anyhow::Result<HeaderTemplate> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Couldn't read {}: {}", path.display(), e))?;
+    HeaderTemplate::parse(&text).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+/// Extensions of the files [`find_patch_files`] picks up.
+const PATCH_EXTENSIONS: [&str; 2] = ["patch", "diff"];
+
+/// Collects every `.patch` and `.diff` file below `dir`, depth first and sorted by name so
+/// the processing order doesn't depend on the filesystem. Symlinks are skipped
+/// unless `follow_symlinks` is set, and then a directory reached twice, e.g.
+/// through a link to one of its parents, is only read the first time.
+fn find_patch_files(dir: &Path, follow_symlinks: bool, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    collect_patch_files(dir, follow_symlinks, &mut HashSet::new(), files)
+}
+
+fn collect_patch_files(
+    dir: &Path,
+    follow_symlinks: bool,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    if !visited.insert(dir.canonicalize()?) {
+        log::debug!("Skipping {}, already visited", dir.display());
+        return Ok(());
+    }
+
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if !follow_symlinks && path.symlink_metadata()?.is_symlink() {
+            log::debug!("Skipping symlink {}", path.display());
+        } else if path.is_dir() {
+            collect_patch_files(&path, follow_symlinks, visited, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| PATCH_EXTENSIONS.iter().any(|known| ext == *known))
+        {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Joins the patches below `dir` into one diff, written to `output` or stdout.
+fn combine(dir: &Path, output: Option<&Path>) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        return Err(anyhow::anyhow!("{} is not a directory", dir.display()));
+    }
+    let mut files = Vec::new();
+    find_patch_files(dir, false, &mut files)?;
+
+    let count = match output {
+        Some(path) => {
+            let mut out = io::BufWriter::new(splitter::create_file(path)?);
+            let count = splitter::combine(&files, &mut out)?;
+            out.flush()?;
+            count
+        }
+        None => splitter::combine(&files, &mut io::stdout().lock())?,
+    };
+    log::info!("Combined {} patches from {} files", count, files.len());
+
+    Ok(())
+}
+
+/// Prints the diffstat of the patches in `files`, or of stdin.
+fn stats(files: &[PathBuf], color: ColorChoice) -> anyhow::Result<()> {
+    let mut stats = Vec::new();
+    if files.is_empty() {
+        stats = spatch::stats::collect(piped_stdin()?)?;
+    }
+    for path in files {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("{} is not a file", path.display()));
+        }
+        stats.extend(spatch::stats::collect(File::open(path)?)?);
+    }
+
+    let painter = Painter::new(color.enabled(io::stdout().is_terminal()));
+    spatch::stats::write_with(&stats, painter, &mut io::stdout().lock())?;
+    Ok(())
+}
+
+/// Checks the hunks of the patches in `files`, or of stdin.
+fn verify(files: &[PathBuf]) -> anyhow::Result<()> {
+    let options = SplitOptions {
+        verify: true,
+        write: false,
+        ..SplitOptions::default()
+    };
+    let mut manifest = Vec::new();
+    let count = if files.is_empty() {
+        splitter::split(piped_stdin()?, &options, "", &mut manifest)?
+    } else {
+        split_files(files, 1, &options, &mut manifest)?
+    };
+    log::info!("Checked {} patches", count.kept);
+
+    Ok(())
+}
+
+/// The `Name <email>` git would author a commit with, if it knows.
+fn git_author() -> Option<String> {
+    let output = process::Command::new("git")
+        .args(["var", "GIT_AUTHOR_IDENT"])
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()?;
+    let ident = String::from_utf8(output.stdout).ok()?;
+    // The timestamp and time zone follow the address.
+    let (author, _) = ident.rsplit_once('>')?;
+    output.status.success().then(|| format!("{}>", author))
+}
+
+/// Stdin, unless nothing is piped through it and reading would just wait for
+/// the user to type a patch.
+fn piped_stdin() -> anyhow::Result<io::StdinLock<'static>> {
+    if io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "No input given. Name the patch files or pipe a patch through stdin"
+        ));
+    }
+    Ok(io::stdin().lock())
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let cli = Cli::parse();
+    log::set_logger(&StderrLogger)?;
+    log::set_max_level(if cli.quiet {
+        log::LevelFilter::Warn
+    } else if cli.verbose {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Info
+    });
+
+    match cli.command {
+        None => split(cli.split, cli.color),
+        Some(Command::Split(args)) => split(*args, cli.color),
+        Some(Command::Combine { dir, output }) => combine(&dir, output.as_deref()),
+        Some(Command::Stats(inputs)) => stats(&inputs.files, cli.color),
+        Some(Command::Verify(inputs)) => verify(&inputs.files),
+    }
+}
+
+fn split(args: SplitArgs, color: ColorChoice) -> anyhow::Result<()> {
     let filter = if args.added_removed.only_new {
         FilterType::OnlyNew(if args.extract_file {
-            FileProcessing::ExtractFile
+            FileProcessing::ExtractFile(ExtractMode::NewSide)
         } else {
             FileProcessing::ExtractPatch
         })
-    } else if args.added_removed.only_new {
+    } else if args.added_removed.only_removed {
         FilterType::OnlyRemoved(if args.extract_file {
-            FileProcessing::ExtractFile
+            FileProcessing::ExtractFile(ExtractMode::OldSide)
         } else {
             FileProcessing::ExtractPatch
         })
     } else if let Some(glob) = args.glob {
-        FilterType::Glob(glob)
+        let glob = globset::GlobBuilder::new(glob.glob())
+            .case_insensitive(args.ignore_case)
+            .build()?;
+        if args.glob_basename {
+            FilterType::GlobBasename(glob)
+        } else {
+            FilterType::Glob(glob)
+        }
     } else if let Some(expr) = args.regex {
-        FilterType::Regex(expr)
+        FilterType::Regex(
+            regex::RegexBuilder::new(expr.as_str())
+                .case_insensitive(args.ignore_case)
+                .build()?,
+        )
     } else {
         FilterType::None
     };
 
+    // Checks on their own write nothing, unless told where to.
+    let write = !(args.verify || args.worktree.is_some())
+        || args.output_dir.is_some()
+        || args.tar.is_some()
+        || args.tar_gz.is_some();
     let output = args.output_dir.unwrap_or(std::env::current_dir()?);
 
-    if !output.is_dir() {
+    // A missing output directory is made along with the first patch written
+    // to it, so inputs without any leave no trace.
+    let missing = !output.exists();
+    if output.exists() && !output.is_dir() {
         return Err(anyhow::anyhow!(
             "Output path {} is not a directory",
             output.display()
         ));
     }
 
-    if !args.files.is_empty() {
-        args.files
-            .iter()
-            .try_for_each(|path| -> anyhow::Result<()> {
-                if !path.is_file() {
-                    return Err(anyhow::anyhow!("{} is not a file", path.display()));
-                }
-
-                println!("Splitting {}", path.display());
-                split_patch(
-                    File::open(path)?,
-                    &filter,
-                    &path
-                        .file_stem()
-                        .unwrap_or_default()
-                        .to_string_lossy()
-                        .into(),
-                    &output,
-                )
-            })
+    let mut options = SplitOptions {
+        filter,
+        content_filter: args
+            .content_regex
+            .map(|regex| ContentFilter::new(regex, args.content_side)),
+        drop_whitespace_only: args.drop_whitespace_only,
+        since: args.since,
+        until: args.until,
+        output_dir: output,
+        extension: args.extension,
+        output_prefix: args.output_prefix,
+        output_suffix: args.output_suffix,
+        encoding: args.encoding,
+        line_ending: args.line_ending,
+        normalize_whitespace: args.normalize_whitespace,
+        reverse: args.reverse,
+        color: color.enabled(io::stdout().is_terminal()),
+        context: args.context,
+        split_by_hunk: args.split_by_hunk,
+        skip: args.skip,
+        max_patches: args.max_patches,
+        chunk_size: args.chunk_size,
+        strip: args.strip,
+        keep_relative: args.keep_relative,
+        lenient: args.lenient,
+        src_prefix: args.no_prefix.then(String::new).or(args.src_prefix),
+        dst_prefix: args.no_prefix.then(String::new).or(args.dst_prefix),
+        preserve_dirs: args.preserve_dirs,
+        group_by_dir: args.group_by_dir,
+        name_from: args.name_from,
+        header_prefix: None,
+        header_template: args
+            .template_file
+            .as_deref()
+            .map(read_template)
+            .transpose()?,
+        include_commit_headers: args.include_commit_headers,
+        mailbox: args.mailbox,
+        mail_author: args.mailbox.then(git_author).flatten(),
+        on_collision: args.on_collision,
+        names: Some(Arc::default()),
+        verify: args.verify,
+        strict: args.strict,
+        write,
+        progress: None,
+        archive: None,
+        checksums: args.checksums.as_ref().map(|_| Arc::default()),
+        dedup: args.dedup.then(Arc::default),
+        sort: args.sort,
+        merge_renames: args.merge_renames,
+        numbered: args.numbered || args.mailbox,
+        worktree: args.worktree,
+    };
+
+    if let Some(path) = &args.tar {
+        let archive = Archive::Tar(splitter::create_file(path)?);
+        options.archive = Some(Arc::new(Mutex::new(tar::Builder::new(archive))));
+    } else if let Some(path) = &args.tar_gz {
+        let encoder = flate2::write::GzEncoder::new(
+            splitter::create_file(path)?,
+            flate2::Compression::default(),
+        );
+        options.archive = Some(Arc::new(Mutex::new(tar::Builder::new(Archive::TarGz(
+            encoder,
+        )))));
+    }
+
+    let mut files = args.files;
+    files.extend(args.trailing_files);
+    let list = match (&args.files_from, &args.files_from0) {
+        (Some(list), _) => Some((list, '\n')),
+        (_, Some(list)) => Some((list, '\0')),
+        _ => None,
+    };
+    if let Some((list, separator)) = list {
+        files.extend(read_file_list(list, separator, args.skip_missing)?);
+    }
+    // Inputs named some other way than on the command line may turn out to be
+    // none at all, which isn't a reason to read stdin instead.
+    let named_inputs = args.input_dir.is_some() || list.is_some();
+    if let Some(dir) = &args.input_dir {
+        if !dir.is_dir() {
+            return Err(anyhow::anyhow!("{} is not a directory", dir.display()));
+        }
+        find_patch_files(dir, args.follow_symlinks, &mut files)?;
+    }
+    // Nothing found to split and no patch piped in, only a terminal to wait on.
+    if files.is_empty() && io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(match named_inputs {
+            true => "No patch files found by --input-dir or --files-from",
+            false => "No input given. Use --files or --input-dir, or pipe a patch through stdin",
+        }));
+    }
+
+    if args.list {
+        let mut out = io::stdout().lock();
+        let mut count = 0;
+        for path in &files {
+            count += splitter::list(File::open(path)?, &options, &mut out)?;
+        }
+        if files.is_empty() && !named_inputs {
+            count = splitter::list(piped_stdin()?, &options, &mut out)?;
+        }
+        if args.fail_on_empty && count == 0 {
+            return Err(anyhow::anyhow!("No patch passed the filters"));
+        }
+        return Ok(());
+    }
+
+    if args.progress {
+        options.progress = Some(if files.is_empty() {
+            ProgressBar::new_spinner()
+                .with_style(ProgressStyle::with_template("{spinner} {pos} patches")?)
+        } else {
+            // One bar for all files, whichever thread reads them.
+            let total = files
+                .iter()
+                .filter_map(|f| f.metadata().ok())
+                .map(|m| m.len())
+                .sum();
+            ProgressBar::new(total).with_style(ProgressStyle::with_template(
+                "{bar:40} {bytes}/{total_bytes} ({eta})",
+            )?)
+        });
+    }
+
+    let mut manifest = Vec::new();
+    let result = if !files.is_empty() {
+        split_files(&files, args.jobs.get(), &options, &mut manifest)
+    } else if named_inputs {
+        Ok(SplitCount::default())
     } else {
-        split_patch(io::stdin().lock(), &filter, &String::new(), &output)
+        let mut stdin = HeadReader {
+            inner: io::stdin().lock(),
+            head: Vec::new(),
+        };
+        splitter::split(&mut stdin, &options, "", &mut manifest).and_then(|count| {
+            if count.seen == 0 {
+                refuse_stat_output(&stdin.head)?;
+            }
+            Ok(count)
+        })
+    };
+
+    if let Some(bar) = &options.progress {
+        bar.finish_and_clear();
+    }
+    // Said once the bar is gone, and only if something was written there.
+    if missing && options.output_dir.is_dir() {
+        log::warn!(
+            "Made the missing output directory {}",
+            options.output_dir.display()
+        );
+    }
+
+    // The splits are done, and so are the clones of the options they made.
+    if let Some(archive) = options.archive.and_then(Arc::into_inner) {
+        archive.into_inner().unwrap().into_inner()?.finish()?;
+    }
+
+    // Whatever did get written is still worth listing if some input failed.
+    if let Some(path) = &args.manifest {
+        splitter::write_manifest(path, &manifest)?;
+    }
+    if let (Some(path), Some(sums)) = (&args.checksums, options.checksums) {
+        splitter::write_checksums(path, &sums.lock().unwrap())?;
+    }
+
+    if let Ok(count) = &result {
+        log::debug!("Kept {} of {} patches", count.kept, count.seen);
+    }
+    if args.fail_on_empty && result.as_ref().is_ok_and(|count| count.kept == 0) {
+        return Err(anyhow::anyhow!("No patch passed the filters"));
     }
+
+    result.map(drop)
 }
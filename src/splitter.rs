@@ -0,0 +1,2256 @@
+use crate::color::{Color, Painter};
+use crate::diff_parser::{
+    DiffParser, Encoding, Hunk, OwnedPatch, Patch, PatchMeta, decode_binary_payload,
+    parse_hunk_header,
+};
+use chrono::{DateTime, FixedOffset};
+use indicatif::ProgressBar;
+use sha2::{Digest, Sha256};
+use std::{
+    cell::Cell,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::{self, Read, Write},
+    num::NonZeroUsize,
+    path::{Component, Path, PathBuf},
+    process::{self, Stdio},
+    sync::{Arc, Mutex},
+};
+
+/// Which side of a diff to rebuild when extracting file contents.
+#[derive(Clone, Debug)]
+pub enum ExtractMode {
+    /// The file after the change: context and added lines.
+    NewSide,
+    /// The file before the change: context and removed lines.
+    OldSide,
+}
+
+/// What to write for the added or removed files picked by
+/// [`FilterType::OnlyNew`] and [`FilterType::OnlyRemoved`].
+#[derive(Clone, Debug)]
+pub enum FileProcessing {
+    ExtractPatch,
+    ExtractFile(ExtractMode),
+}
+
+/// Which patches to split off, by the paths of their files.
+#[derive(Clone, Debug)]
+pub enum FilterType {
+    Regex(regex::Regex),
+    Glob(globset::Glob),
+    /// A glob matched against the file names only, so `*.rs` picks
+    /// `main.rs` in any directory and `src/*.rs` picks nothing.
+    GlobBasename(globset::Glob),
+    OnlyNew(FileProcessing),
+    OnlyRemoved(FileProcessing),
+    None,
+}
+
+/// A patch as the parser streams it, or with its body already read.
+enum Body<T: Sized + Read> {
+    Streamed(Patch<T>),
+    Buffered(OwnedPatch),
+}
+
+impl<T: Sized + Read> Body<T> {
+    fn meta(&self) -> PatchMeta {
+        match self {
+            Body::Streamed(patch) => patch.meta(),
+            Body::Buffered(patch) => patch.meta(),
+        }
+    }
+
+    fn has_prefixes(&self) -> bool {
+        match self {
+            Body::Streamed(patch) => patch.has_prefixes(),
+            Body::Buffered(patch) => patch.has_prefixes(),
+        }
+    }
+
+    fn commit_block(&self) -> Option<String> {
+        match self {
+            Body::Streamed(p) => commit_block(p.commit_hash(), p.author(), p.commit_message()),
+            Body::Buffered(p) => commit_block(p.commit_hash(), p.author(), p.commit_message()),
+        }
+    }
+
+    /// The mail for `git am` around the patch, see [`mail_envelope`]. The
+    /// author and subject stand in for those of a missing commit.
+    fn mail_envelope(&self, author: Option<&str>, subject: &str) -> Option<String> {
+        let (hash, commit_author, date, message) = match self {
+            Body::Streamed(p) => (
+                p.commit_hash(),
+                p.author(),
+                p.commit_date(),
+                p.commit_message(),
+            ),
+            Body::Buffered(p) => (
+                p.commit_hash(),
+                p.author(),
+                p.commit_date(),
+                p.commit_message(),
+            ),
+        };
+        let author = commit_author.or(author)?;
+        Some(mail_envelope(
+            hash,
+            author,
+            date,
+            message.unwrap_or(subject),
+        ))
+    }
+
+    /// Reads the body through, if it's still streamed.
+    fn into_owned(self) -> OwnedPatch {
+        match self {
+            Body::Streamed(patch) => patch.into_owned(),
+            Body::Buffered(patch) => patch,
+        }
+    }
+}
+
+/// Which body lines `--content-regex` is matched against.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum ContentSide {
+    Added,
+    Removed,
+    Both,
+}
+
+/// Keeps only patches with an added or removed line matching `regex`.
+#[derive(Clone, Debug)]
+pub struct ContentFilter {
+    regex: regex::Regex,
+    side: ContentSide,
+}
+
+impl ContentFilter {
+    pub fn new(regex: regex::Regex, side: ContentSide) -> Self {
+        ContentFilter { regex, side }
+    }
+
+    fn is_match(&self, lines: &[String]) -> bool {
+        lines.iter().any(|line| {
+            let text = match (self.side, line.chars().next()) {
+                (ContentSide::Added | ContentSide::Both, Some('+'))
+                | (ContentSide::Removed | ContentSide::Both, Some('-')) => &line[1..],
+                _ => return false,
+            };
+            self.regex.is_match(text.strip_suffix('\r').unwrap_or(text))
+        })
+    }
+}
+
+/// What stands in for the header of every patch written: text with
+/// `{original_header}`, `{filename}`, `{additions}` and `{deletions}` filled
+/// in. `{{` and `}}` write a brace.
+#[derive(Clone, Debug)]
+pub struct HeaderTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Clone, Debug)]
+enum TemplatePart {
+    Text(String),
+    OriginalHeader,
+    Filename,
+    Additions,
+    Deletions,
+}
+
+impl HeaderTemplate {
+    /// Fails on placeholders other than the four known ones, and on braces
+    /// that don't close.
+    pub fn parse(template: &str) -> anyhow::Result<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(i) = rest.find(['{', '}']) {
+            text.push_str(&rest[..i]);
+            rest = &rest[i..];
+            if let Some(after) = rest.strip_prefix("{{").or(rest.strip_prefix("}}")) {
+                text.push_str(&rest[..1]);
+                rest = after;
+                continue;
+            }
+            let Some((name, after)) = rest.strip_prefix('{').and_then(|r| r.split_once('}')) else {
+                return Err(anyhow::anyhow!(
+                    "unmatched '{}' in the template, write {{{{ or }}}} for a brace",
+                    &rest[..1]
+                ));
+            };
+            let part = match name {
+                "original_header" => TemplatePart::OriginalHeader,
+                "filename" => TemplatePart::Filename,
+                "additions" => TemplatePart::Additions,
+                "deletions" => TemplatePart::Deletions,
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "unknown placeholder {{{}}} in the template, expected one of {{original_header}}, {{filename}}, {{additions}} or {{deletions}}",
+                        name
+                    ));
+                }
+            };
+            if !text.is_empty() {
+                parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+            }
+            parts.push(part);
+            rest = after;
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+        Ok(HeaderTemplate { parts })
+    }
+
+    /// The header for the patch of `filename`, which adds `additions` lines
+    /// and removes `deletions`. Ends in exactly one line break, as the body
+    /// follows right after it.
+    fn render(
+        &self,
+        original_header: &str,
+        filename: &str,
+        additions: usize,
+        deletions: usize,
+    ) -> String {
+        let mut header = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(text) => header.push_str(text),
+                TemplatePart::OriginalHeader => header.push_str(original_header),
+                TemplatePart::Filename => header.push_str(filename),
+                TemplatePart::Additions => header.push_str(&additions.to_string()),
+                TemplatePart::Deletions => header.push_str(&deletions.to_string()),
+            }
+        }
+        header.truncate(header.trim_end_matches('\n').len());
+        if !header.is_empty() {
+            header.push('\n');
+        }
+        header
+    }
+}
+
+/// Line terminator used for the generated files.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum LineEnding {
+    /// Keep whatever each input line was terminated with.
+    Auto,
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// Splits the `\r` a CRLF input leaves at the end of `line` off and picks
+    /// the terminator to write after the remaining text.
+    fn split(self, line: &str) -> (&str, &'static str) {
+        let (text, cr) = match line.strip_suffix('\r') {
+            Some(text) => (text, true),
+            None => (line, false),
+        };
+
+        match (self, cr) {
+            (LineEnding::Crlf, _) | (LineEnding::Auto, true) => (text, "\r\n"),
+            _ => (text, "\n"),
+        }
+    }
+}
+
+/// The order [`SplitOptions::sort`] puts the patches of an input in. Patches
+/// that tie keep their order in the input.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum SortKey {
+    /// By the name of the diffed file.
+    Name,
+    /// Most added lines first.
+    Additions,
+    /// Most removed lines first.
+    Deletions,
+    /// Most added and removed lines together first.
+    Total,
+}
+
+/// How many of the body `lines` add a line, with `prefix` `+`, or remove one,
+/// with `-`.
+fn count_lines(lines: &[String], prefix: char) -> usize {
+    lines.iter().filter(|l| l.starts_with(prefix)).count()
+}
+
+/// Sorts `patches` by `key`, see [`SortKey`].
+fn sort_patches(patches: &mut [OwnedPatch], key: SortKey) {
+    let count = |patch: &OwnedPatch, prefix: char| count_lines(patch.lines(), prefix);
+    match key {
+        SortKey::Name => patches.sort_by_cached_key(|p| p.display_name()),
+        SortKey::Additions => patches.sort_by_cached_key(|p| Reverse(count(p, '+'))),
+        SortKey::Deletions => patches.sort_by_cached_key(|p| Reverse(count(p, '-'))),
+        SortKey::Total => patches.sort_by_cached_key(|p| Reverse(count(p, '+') + count(p, '-'))),
+    }
+}
+
+/// Replaces every removed file and an added file with the same contents by a
+/// rename, see [`OwnedPatch::merge_rename`]. The rename takes the place of the
+/// added file, where `git diff -M` puts it. Returns how many renames were made.
+fn merge_renames(patches: &mut Vec<OwnedPatch>) -> usize {
+    let mut merged = 0;
+    let mut i = 0;
+    while i < patches.len() {
+        let found = (0..patches.len())
+            .filter(|&j| j != i)
+            .find_map(|j| Some((j, OwnedPatch::merge_rename(&patches[i], &patches[j])?)));
+        match found {
+            Some((j, rename)) => {
+                patches[j] = rename;
+                // The next patch takes the removed file's place.
+                patches.remove(i);
+                merged += 1;
+            }
+            None => i += 1,
+        }
+    }
+    merged
+}
+
+/// Which path of a patch its output is named after. Only renames, copies and
+/// the files missing one side tell them apart.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum NameFrom {
+    /// The new path, or the old one of a removed file, as `new` does.
+    Auto,
+    /// The new path, or the old one of a removed file.
+    New,
+    /// The old path, or the new one of an added file, e.g. to keep track
+    /// of the files a change removes or moves away.
+    Old,
+}
+
+impl NameFrom {
+    fn pick<'a>(self, old: Option<&'a str>, new: Option<&'a str>) -> Option<&'a str> {
+        match self {
+            NameFrom::Auto | NameFrom::New => new.or(old),
+            NameFrom::Old => old.or(new),
+        }
+    }
+}
+
+/// What to do when two patches of an input end up with the same name, e.g.
+/// because flattening `a/b-c` and `a-b/c` gives `a-b-c` for both.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum OnCollision {
+    /// Fail the split.
+    Error,
+    /// Suffix every name after the first with `~N`.
+    Number,
+    /// Write over the earlier patch.
+    Overwrite,
+}
+
+/// How often each output name came up, and for which file first.
+pub type NameCounts = HashMap<String, (usize, String)>;
+
+/// How a file was changed, as recorded in the manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Add,
+    Delete,
+    Modify,
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Change::Add => "add",
+            Change::Delete => "delete",
+            Change::Modify => "modify",
+        })
+    }
+}
+
+/// How many patches [`split`] read off an input, and how many of them passed
+/// the filters and were written, or only checked with `--verify`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SplitCount {
+    pub seen: usize,
+    pub kept: usize,
+}
+
+/// One written file: which diffed file it came from and where it ended up,
+/// relative to the output directory.
+#[derive(Clone, Debug)]
+pub struct ManifestEntry {
+    pub source: String,
+    pub output: PathBuf,
+    pub change: Change,
+}
+
+/// Writes `entries` as tab separated `path`, `output` and `change` columns,
+/// preceded by a header row.
+pub fn write_manifest(path: &Path, entries: &[ManifestEntry]) -> io::Result<()> {
+    let mut out = io::BufWriter::new(create_file(path)?);
+    writeln!(out, "path\toutput\tchange")?;
+    for entry in entries {
+        writeln!(
+            out,
+            "{}\t{}\t{}",
+            entry.source,
+            entry.output.display(),
+            entry.change
+        )?;
+    }
+    out.flush()
+}
+
+/// Writes `sums` the way `sha256sum` does, a hash and two spaces before every
+/// path, so that `sha256sum -c` run in the output directory checks them.
+pub fn write_checksums(path: &Path, sums: &BTreeMap<PathBuf, String>) -> io::Result<()> {
+    let mut out = io::BufWriter::new(create_file(path)?);
+    for (file, sum) in sums {
+        writeln!(out, "{}  {}", sum, file.display())?;
+    }
+    out.flush()
+}
+
+/// Everything [`split`] needs to know besides the input itself. Clones share
+/// the archive and the sets filled in along the way.
+#[derive(Clone)]
+pub struct SplitOptions {
+    /// How the input is decoded. Output is written in the same encoding.
+    pub encoding: Encoding,
+    pub filter: FilterType,
+    /// Also filter on the patch bodies, on top of `filter`.
+    pub content_filter: Option<ContentFilter>,
+    /// Leave out hunks that only change whitespace, and patches left without
+    /// any hunks because of it.
+    pub drop_whitespace_only: bool,
+    /// Only keep patches of commits made in this range, bounds included.
+    /// Patches without a commit date are left out if either bound is set.
+    pub since: Option<DateTime<FixedOffset>>,
+    pub until: Option<DateTime<FixedOffset>>,
+    pub output_dir: PathBuf,
+    /// Appended to generated patch names unless they already end with it.
+    pub extension: String,
+    /// Put before and after the file name of every patch, ahead of the
+    /// extension. Extracted files keep their names.
+    pub output_prefix: String,
+    pub output_suffix: String,
+    pub line_ending: LineEnding,
+    /// Trim trailing whitespace off added lines.
+    pub normalize_whitespace: bool,
+    /// Write every patch the other way around, undoing its changes, like
+    /// `git diff -R` would have. Names stay those of the patches as read.
+    pub reverse: bool,
+    /// Color the `A`, `M`, `D`, `R` and `C` markers written by [`list`].
+    pub color: bool,
+    /// Cut the context around the changes of every hunk down to this many
+    /// lines.
+    pub context: Option<u32>,
+    /// Write every hunk to its own file, suffixed with `#N`.
+    pub split_by_hunk: bool,
+    /// How many of the patches passing the filters to leave out, per input.
+    pub skip: usize,
+    /// Stop after this many patches, per input.
+    pub max_patches: Option<usize>,
+    /// Write this many consecutive patches into each file, named
+    /// `chunk-0001` and so on, instead of one file per patch.
+    pub chunk_size: Option<NonZeroUsize>,
+    /// Leading path components dropped from the diffed files' paths.
+    pub strip: usize,
+    /// Keep the leading `./` or `../` of the diffed files' paths, see
+    /// [`DiffParser::keep_relative`].
+    pub keep_relative: bool,
+    /// Split a leading fragment without its `diff --git` line too, see
+    /// [`DiffParser::lenient`].
+    pub lenient: bool,
+    /// Replace the `a/` and `b/` prefixes of the paths in the headers.
+    pub src_prefix: Option<String>,
+    pub dst_prefix: Option<String>,
+    /// Recreate the directories of the diffed files instead of flattening
+    /// their paths with `-`.
+    pub preserve_dirs: bool,
+    /// Put every patch in a directory named after the first component of its
+    /// path, flattening the rest. Does nothing with `preserve_dirs`, which
+    /// keeps every directory anyway.
+    pub group_by_dir: bool,
+    /// Which path of a patch names its output.
+    pub name_from: NameFrom,
+    /// Written above the header of every patch, e.g. a `# Extracted by ...`
+    /// comment. `git apply` skips whatever comes before `diff --git`, as long
+    /// as it doesn't look like a diff itself. Left out of extracted files.
+    pub header_prefix: Option<String>,
+    /// Written instead of the header of every patch, which it can still take
+    /// in. Comes below `header_prefix` and the commit. Left out of extracted
+    /// files.
+    pub header_template: Option<HeaderTemplate>,
+    /// Write the commit a patch came from above its header, for `git log -p`
+    /// and `git format-patch` input. Left out of extracted files.
+    pub include_commit_headers: bool,
+    /// Wrap every patch in a mail `git am` takes, made of the commit it came
+    /// from or, without one, of a subject naming the file and `mail_author`.
+    pub mailbox: bool,
+    /// Author of the mails of patches that came without their commit, as
+    /// `Name <email>`. The split fails on such a patch if this is missing.
+    pub mail_author: Option<String>,
+    pub on_collision: OnCollision,
+    /// The names given so far. Inputs sharing it are checked for collisions
+    /// with each other too, in the order they are split; without it each
+    /// split only checks its own patches.
+    pub names: Option<Arc<Mutex<NameCounts>>>,
+    /// Check that every hunk's body matches the line counts in its header.
+    pub verify: bool,
+    /// Fail on inputs that look damaged: ones ending in the middle of a hunk,
+    /// like a cut off download, with a patch naming its file twice, or with a
+    /// line that isn't UTF-8 under [`Encoding::Utf8Strict`].
+    pub strict: bool,
+    /// Whether to write anything at all; `--verify` on its own only checks.
+    pub write: bool,
+    /// Counts bytes read when the input size is known, patches otherwise.
+    pub progress: Option<ProgressBar>,
+    /// Write into this archive rather than into `output_dir`.
+    pub archive: Option<Arc<Mutex<tar::Builder<Archive>>>>,
+    /// Collects the SHA-256 of every file written, by its path in the output
+    /// directory or archive. Shared by the writers of all threads.
+    pub checksums: Option<Arc<Mutex<BTreeMap<PathBuf, String>>>>,
+    /// Skip patches whose header and body are byte-equal to one written
+    /// before, after trimming added lines with `normalize_whitespace`. Holds
+    /// the SHA-256 of every patch let through. Inputs split one after another
+    /// can share it, those split side by side each need their own, seeded
+    /// with the patches of the inputs before them, see [`digests`].
+    pub dedup: Option<Arc<Mutex<HashSet<[u8; 32]>>>>,
+    /// Write the patches of each input in this order rather than as read.
+    /// Every patch of an input is then held in memory, body included, until
+    /// the last one is read; without sorting they are streamed one by one.
+    pub sort: Option<SortKey>,
+    /// Turn a removed file and an added one with the same contents into a
+    /// rename, for diffs made without rename detection. Like sorting, this
+    /// holds every patch of an input in memory.
+    pub merge_renames: bool,
+    /// Put the position of every patch among those written for its input,
+    /// `0001-` for the first, in front of its name, like `git format-patch`.
+    /// Can't be combined with `chunk_size`.
+    pub numbered: bool,
+    /// Check that every patch applies to the working tree in this directory
+    /// with `git apply --check`, failing the split if any doesn't. Patches are
+    /// checked as written, even if `write` is off.
+    pub worktree: Option<PathBuf>,
+}
+
+/// Writes every patch into the current directory, like spatch without any
+/// options does.
+impl Default for SplitOptions {
+    fn default() -> Self {
+        SplitOptions {
+            filter: FilterType::None,
+            content_filter: None,
+            drop_whitespace_only: false,
+            since: None,
+            until: None,
+            output_dir: PathBuf::from("."),
+            extension: "patch".to_string(),
+            output_prefix: String::new(),
+            output_suffix: String::new(),
+            line_ending: LineEnding::Auto,
+            normalize_whitespace: false,
+            reverse: false,
+            color: false,
+            context: None,
+            split_by_hunk: false,
+            skip: 0,
+            max_patches: None,
+            chunk_size: None,
+            strip: 0,
+            keep_relative: false,
+            lenient: false,
+            src_prefix: None,
+            dst_prefix: None,
+            encoding: Encoding::Utf8Strict,
+            preserve_dirs: false,
+            group_by_dir: false,
+            name_from: NameFrom::Auto,
+            header_prefix: None,
+            header_template: None,
+            include_commit_headers: false,
+            mailbox: false,
+            mail_author: None,
+            on_collision: OnCollision::Number,
+            names: None,
+            verify: false,
+            strict: false,
+            write: true,
+            progress: None,
+            archive: None,
+            checksums: None,
+            dedup: None,
+            sort: None,
+            merge_renames: false,
+            numbered: false,
+            worktree: None,
+        }
+    }
+}
+
+impl SplitOptions {
+    /// The writer for `archive`, if there is one, or for `output_dir`. One
+    /// that writes nothing if nothing is to be written.
+    fn writer(&self) -> Box<dyn PatchWriter + '_> {
+        match &self.archive {
+            _ if !self.write => Box::new(Discard),
+            Some(archive) => Box::new(ArchiveWriter::new(archive)),
+            None => Box::new(DirWriter::new(&self.output_dir)),
+        }
+    }
+}
+
+/// The file behind `--tar` or `--tar-gz`.
+pub enum Archive {
+    Tar(File),
+    TarGz(flate2::write::GzEncoder<File>),
+}
+
+impl Archive {
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Archive::Tar(file) => file.sync_all(),
+            Archive::TarGz(encoder) => encoder.finish()?.sync_all(),
+        }
+    }
+}
+
+impl Write for Archive {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Archive::Tar(file) => file.write(buf),
+            Archive::TarGz(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Archive::Tar(file) => file.flush(),
+            Archive::TarGz(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Receives the files a split writes, one at a time: each `begin` is followed
+/// by the lines of the file and a `finish`. Paths are relative to the output
+/// directory.
+pub trait PatchWriter {
+    fn begin(&mut self, path: &Path) -> io::Result<()>;
+    /// Adds `line`, terminator included, to the file begun last.
+    fn write_line(&mut self, line: &str) -> io::Result<()>;
+    /// Like [`PatchWriter::write_line`], for lines that may not be UTF-8, as
+    /// read with [`Encoding::Bytes`]. Writers that only handle text get them
+    /// with invalid sequences replaced.
+    fn write_bytes(&mut self, line: &[u8]) -> io::Result<()> {
+        self.write_line(&String::from_utf8_lossy(line))
+    }
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Hands lines read with [`Encoding::Bytes`] on as the bytes they were.
+struct RawBytes<'a>(&'a mut dyn PatchWriter);
+
+impl PatchWriter for RawBytes<'_> {
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        self.0.begin(path)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.0.write_bytes(&Encoding::Bytes.encode(line))
+    }
+
+    fn write_bytes(&mut self, line: &[u8]) -> io::Result<()> {
+        self.0.write_bytes(line)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.0.finish()
+    }
+}
+
+/// Appends every file begun under the same path to the same file, so that
+/// the patches of a chunk end up together. [`Chunks::close`] finishes the last
+/// one.
+struct Chunks<'a> {
+    inner: &'a mut dyn PatchWriter,
+    current: Option<PathBuf>,
+}
+
+impl Chunks<'_> {
+    fn close(&mut self) -> io::Result<()> {
+        match self.current.take() {
+            Some(_) => self.inner.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl PatchWriter for Chunks<'_> {
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        if self.current.as_deref() != Some(path) {
+            self.close()?;
+            self.inner.begin(path)?;
+            self.current = Some(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.inner.write_line(line)
+    }
+
+    fn write_bytes(&mut self, line: &[u8]) -> io::Result<()> {
+        self.inner.write_bytes(line)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hashes the exact bytes of every file handed on to `inner`, adding their
+/// sums to `sums` as the files are finished.
+struct Checksums<'a> {
+    inner: &'a mut dyn PatchWriter,
+    sums: &'a Mutex<BTreeMap<PathBuf, String>>,
+    path: PathBuf,
+    hasher: Sha256,
+}
+
+impl PatchWriter for Checksums<'_> {
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        self.path = path.to_path_buf();
+        self.hasher = Sha256::new();
+        self.inner.begin(path)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.hasher.update(line.as_bytes());
+        self.inner.write_line(line)
+    }
+
+    fn write_bytes(&mut self, line: &[u8]) -> io::Result<()> {
+        self.hasher.update(line);
+        self.inner.write_bytes(line)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.finish()?;
+        let sum = std::mem::take(&mut self.hasher).finalize();
+        let sum = sum.iter().map(|b| format!("{:02x}", b)).collect();
+        // A file written again, e.g. with `OnCollision::Overwrite`, keeps
+        // the sum of what it ended up holding.
+        self.sums
+            .lock()
+            .unwrap()
+            .insert(std::mem::take(&mut self.path), sum);
+        Ok(())
+    }
+}
+
+/// Checks that every file handed on to `inner` applies to the working tree in
+/// `dir`, by piping it through `git apply --check`. Binary patches aren't
+/// checked.
+struct ApplyCheck<'a> {
+    inner: &'a mut dyn PatchWriter,
+    dir: &'a Path,
+    /// How many leading components `git apply` strips off the paths, set for
+    /// every patch as it's written.
+    strip: &'a Cell<usize>,
+    path: PathBuf,
+    patch: Vec<u8>,
+    failures: usize,
+}
+
+impl PatchWriter for ApplyCheck<'_> {
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        self.path = path.to_path_buf();
+        self.patch.clear();
+        self.inner.begin(path)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.patch.extend_from_slice(line.as_bytes());
+        self.inner.write_line(line)
+    }
+
+    fn write_bytes(&mut self, line: &[u8]) -> io::Result<()> {
+        self.patch.extend_from_slice(line);
+        self.inner.write_bytes(line)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.finish()?;
+        let binary = self.patch.split(|&b| b == b'\n').any(|line| {
+            line.starts_with(b"GIT binary patch") || line.starts_with(b"Binary files ")
+        });
+        if binary {
+            log::info!(
+                "{}: binary, not checked against {}",
+                self.path.display(),
+                self.dir.display()
+            );
+            return Ok(());
+        }
+
+        let mut child = process::Command::new("git")
+            .args(["apply", "--check", &format!("-p{}", self.strip.get()), "-"])
+            .current_dir(self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("couldn't run git apply: {}", e)))?;
+        // git reads all of the patch before it writes a word, so nothing
+        // blocks on a full pipe.
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(&self.patch)?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            self.failures += 1;
+            log::error!(
+                "{} doesn't apply to {}: {}",
+                self.path.display(),
+                self.dir.display(),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Writes nothing, for checks that need the patches but not the files.
+struct Discard;
+
+impl PatchWriter for Discard {
+    fn begin(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_line(&mut self, _line: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes files below a directory, streaming their lines to disk.
+pub struct DirWriter {
+    dir: PathBuf,
+    file: Option<io::BufWriter<File>>,
+}
+
+impl DirWriter {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        DirWriter {
+            dir: dir.into(),
+            file: None,
+        }
+    }
+}
+
+impl PatchWriter for DirWriter {
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        let file = create_file(&self.dir.join(path))?;
+        self.file = Some(io::BufWriter::new(file));
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.write_bytes(line.as_bytes())
+    }
+
+    fn write_bytes(&mut self, line: &[u8]) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.write_all(line),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.file.take() {
+            Some(mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Adds files to an archive, which can be shared by the writers of several
+/// threads. Entries need their size up front, so each file is held in memory
+/// until it's finished.
+pub struct ArchiveWriter<'a> {
+    archive: &'a Mutex<tar::Builder<Archive>>,
+    name: PathBuf,
+    data: Vec<u8>,
+}
+
+impl<'a> ArchiveWriter<'a> {
+    pub fn new(archive: &'a Mutex<tar::Builder<Archive>>) -> Self {
+        ArchiveWriter {
+            archive,
+            name: PathBuf::new(),
+            data: Vec::new(),
+        }
+    }
+}
+
+impl PatchWriter for ArchiveWriter<'_> {
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        self.name = path.to_path_buf();
+        self.data.clear();
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.write_bytes(line.as_bytes())
+    }
+
+    fn write_bytes(&mut self, line: &[u8]) -> io::Result<()> {
+        self.data.extend_from_slice(line);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(self.data.len() as u64);
+        header.set_mode(0o644);
+        self.archive
+            .lock()
+            .unwrap()
+            .append_data(&mut header, &self.name, self.data.as_slice())
+    }
+}
+
+/// Whether the commit `patch` came from was made between `options.since` and
+/// `options.until`.
+fn in_date_range<T: Sized + Read>(patch: &Patch<T>, options: &SplitOptions) -> bool {
+    if options.since.is_none() && options.until.is_none() {
+        return true;
+    }
+    patch.commit_date().is_some_and(|date| {
+        options.since.is_none_or(|since| date >= since)
+            && options.until.is_none_or(|until| date <= until)
+    })
+}
+
+fn should_skip_patch<T: Sized + Read>(patch: &Patch<T>, filter: &FilterType) -> bool {
+    match filter {
+        FilterType::None => false,
+        FilterType::Glob(glob) => !patch.matches_glob(&glob.compile_matcher()),
+        FilterType::GlobBasename(glob) => !patch.matches_basename_glob(&glob.compile_matcher()),
+        FilterType::Regex(expr) => !patch.matches_regex(expr),
+        // A copy's destination didn't exist before either.
+        FilterType::OnlyNew(_) => patch.old_path().is_some() && patch.copy().is_none(),
+        FilterType::OnlyRemoved(_) => patch.new_path().is_some(),
+    }
+}
+
+/// Writes one side of the patch body, i.e. the file as it looks before or
+/// after the change. Returns `false` when the hunks also carry context or
+/// lines of the other side, meaning they don't describe the whole file and
+/// what was written is only partial.
+fn write_side(
+    out: &mut dyn PatchWriter,
+    lines: impl Iterator<Item = String>,
+    mode: &ExtractMode,
+    ending: LineEnding,
+) -> io::Result<bool> {
+    let (ours, theirs) = match mode {
+        ExtractMode::NewSide => ('+', '-'),
+        ExtractMode::OldSide => ('-', '+'),
+    };
+    let mut complete = true;
+    // The last line written, still waiting for its terminator.
+    let mut pending: Option<(String, &str)> = None;
+    let mut last = ' ';
+
+    for line in lines {
+        if line.starts_with("@@ -") {
+            continue;
+        }
+
+        let (text, eol) = ending.split(&line);
+        // An empty line is a context line whose leading space got trimmed.
+        let kind = text.chars().next().unwrap_or(' ');
+        if kind == '\\' {
+            // "\ No newline at end of file" applies to the line right before it.
+            if last != theirs
+                && let Some((_, eol)) = pending.as_mut()
+            {
+                *eol = "";
+            }
+            continue;
+        }
+
+        last = kind;
+        if kind != ours {
+            complete = false;
+        }
+        if kind == theirs {
+            continue;
+        }
+
+        if let Some((text, eol)) = pending {
+            out.write_line(&(text + eol))?;
+        }
+        pending = Some((text.get(1..).unwrap_or_default().to_string(), eol));
+    }
+
+    if let Some((text, eol)) = pending {
+        out.write_line(&(text + eol))?;
+    }
+
+    Ok(complete)
+}
+
+fn write_line(out: &mut dyn PatchWriter, line: &str, ending: LineEnding) -> io::Result<()> {
+    let (text, eol) = ending.split(line);
+    out.write_line(&format!("{}{}", text, eol))
+}
+
+/// The SHA-256 of what [`SplitOptions::dedup`] compares: the header, the
+/// binary payload and the body of `patch`, with the added lines trimmed if
+/// `normalize` is set. The blob names of the `index` line then differ along
+/// with the untrimmed lines, so that line is left out. Every body line is
+/// hashed with its `\n`, or bodies split differently would hash the same.
+fn patch_digest(patch: &OwnedPatch, normalize: bool) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for line in patch.header().split_inclusive('\n') {
+        if !(normalize && line.starts_with("index ")) {
+            hasher.update(line);
+        }
+    }
+    hasher.update(patch.binary_payload().unwrap_or_default());
+    for line in patch.lines() {
+        let mut line = line.clone();
+        if normalize {
+            trim_added_line(&mut line);
+        }
+        hasher.update(line);
+        hasher.update("\n");
+    }
+    hasher.finalize().into()
+}
+
+/// Trims the trailing spaces and tabs off `line` if it's an added one,
+/// keeping its `+` and the `\r` of CRLF input. Returns whether anything was
+/// trimmed. Context and removed lines have to match the file the patch applies
+/// to, so they are left alone.
+fn trim_added_line(line: &mut String) -> bool {
+    if !line.starts_with('+') {
+        return false;
+    }
+    let cr = line.ends_with('\r');
+    let text = line.strip_suffix('\r').unwrap_or(line);
+    let len = text.trim_end_matches([' ', '\t']).len();
+    if len == text.len() {
+        return false;
+    }
+
+    line.truncate(len);
+    if cr {
+        line.push('\r');
+    }
+    true
+}
+
+/// Cuts the context around the changes of each hunk in `lines`, the body of a
+/// patch with the given `hunks`, down to `n` lines and fixes up the hunk
+/// headers to match. Hunks with changes more than `2n` context lines apart
+/// would have to be split in two, so they're left as they are and their
+/// headers are added to `kept`. So are hunks whose body doesn't match their
+/// header.
+fn reduce_context(lines: &[String], hunks: &[Hunk], n: u32, kept: &mut Vec<String>) -> Vec<String> {
+    // An empty line is a context line whose leading space got trimmed.
+    let kind = |line: &String| match line.chars().next() {
+        None | Some('\r') => ' ',
+        Some(c) => c,
+    };
+    let mut out = Vec::with_capacity(lines.len());
+    let mut hunks = hunks.iter();
+    let mut rest = lines;
+
+    while let Some((first, tail)) = rest.split_first() {
+        let end = tail
+            .iter()
+            .position(|l| l.starts_with("@@ -"))
+            .unwrap_or(tail.len());
+        let (body, next) = tail.split_at(end);
+        rest = next;
+        let Some(hunk) = hunks.next().filter(|_| first.starts_with("@@ -")) else {
+            out.push(first.clone());
+            out.extend_from_slice(body);
+            continue;
+        };
+
+        // Context lines before the first change, after the last one and the
+        // longest run in between.
+        let (mut lead, mut trail, mut gap) = (0u32, 0u32, 0);
+        let mut changed = false;
+        for line in body {
+            match kind(line) {
+                ' ' if changed => trail += 1,
+                ' ' => lead += 1,
+                '-' | '+' => {
+                    changed = true;
+                    gap = gap.max(trail);
+                    trail = 0;
+                }
+                _ => {}
+            }
+        }
+
+        let (drop_lead, drop_trail) = (lead.saturating_sub(n), trail.saturating_sub(n));
+        if !changed || (drop_lead == 0 && drop_trail == 0) || !hunk.is_consistent() {
+            out.push(first.clone());
+            out.extend_from_slice(body);
+            continue;
+        }
+        if gap > n.saturating_mul(2) {
+            kept.push(hunk.header().to_string());
+            out.push(first.clone());
+            out.extend_from_slice(body);
+            continue;
+        }
+
+        // An empty range starts at the line before it, as in `-5,0`.
+        let range = |start: u32, len: u32| match len {
+            0 => format!("{},0", start + drop_lead - 1),
+            1 => format!("{}", start + drop_lead),
+            _ => format!("{},{}", start + drop_lead, len),
+        };
+        let old_len = hunk.old_len() - drop_lead - drop_trail;
+        let new_len = hunk.new_len() - drop_lead - drop_trail;
+        let section = hunk.section();
+        out.push(format!(
+            "@@ -{} +{} @@{}{}",
+            range(hunk.old_start(), old_len),
+            range(hunk.new_start(), new_len),
+            section,
+            if first.ends_with('\r') { "\r" } else { "" }
+        ));
+
+        let mut body = &body[drop_lead as usize..];
+        // A "\ No newline at end of file" goes with the context line before it.
+        let mut dropped = 0;
+        while dropped < drop_trail
+            && let Some((last, init)) = body.split_last()
+        {
+            if kind(last) == ' ' {
+                dropped += 1;
+            }
+            body = init;
+        }
+        out.extend_from_slice(body);
+    }
+
+    out
+}
+
+/// Appends `.{extension}` to `name` unless it's already there, so that diffs
+/// of `.patch` files don't end up as `foo.patch.patch`.
+fn with_extension(name: String, extension: &str) -> PathBuf {
+    if extension.is_empty() || name.ends_with(&format!(".{}", extension)) {
+        PathBuf::from(name)
+    } else {
+        PathBuf::from(name).with_added_extension(extension)
+    }
+}
+
+/// Creates `path`, along with any of its missing parent directories.
+pub fn create_file(path: &Path) -> io::Result<File> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    File::create(path)
+}
+
+fn write_text(out: &mut dyn PatchWriter, text: &str, ending: LineEnding) -> io::Result<()> {
+    text.split_terminator('\n')
+        .try_for_each(|line| write_line(out, line, ending))
+}
+
+/// Drops the first `n` components of `path`, the way `patch -p<n>` does.
+fn strip_components(path: Option<&str>, n: usize) -> anyhow::Result<Option<String>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+
+    match path.splitn(n + 1, '/').nth(n) {
+        Some(rest) if !rest.is_empty() => Ok(Some(rest.to_string())),
+        _ => Err(anyhow::anyhow!(
+            "cannot strip {} leading components from '{}'",
+            n,
+            path
+        )),
+    }
+}
+
+/// The name a patch is written under, before the extension: `path`, the
+/// patch's display name, as the path of the extracted file, or with the input
+/// file's name appended. Patch file names get the output prefix and suffix
+/// around them.
+fn output_name(path: &str, options: &SplitOptions, patchfile: &str) -> String {
+    let name = match &options.filter {
+        FilterType::OnlyNew(FileProcessing::ExtractFile(_))
+        | FilterType::OnlyRemoved(FileProcessing::ExtractFile(_)) => path.to_string(),
+        _ if patchfile.is_empty() => path.to_string(),
+        _ => format!("{}+{}", path, patchfile),
+    };
+
+    let name = if options.preserve_dirs {
+        name
+    } else if options.group_by_dir
+        && let Some((dir, rest)) = name.split_once('/')
+    {
+        format!("{}/{}", dir, rest.replace("/", "-"))
+    } else {
+        name.replace("/", "-")
+    };
+
+    if let FilterType::OnlyNew(FileProcessing::ExtractFile(_))
+    | FilterType::OnlyRemoved(FileProcessing::ExtractFile(_)) = options.filter
+    {
+        return name;
+    }
+    let (prefix, suffix) = (&options.output_prefix, &options.output_suffix);
+    match name.rsplit_once('/') {
+        Some((dir, file)) => format!("{}/{}{}{}", dir, prefix, file, suffix),
+        None => format!("{}{}{}", prefix, name, suffix),
+    }
+}
+
+/// The name of the `n`th chunk of patches, counting from 1, with the input
+/// file's name appended like [`output_name`] does.
+fn chunk_name(n: usize, patchfile: &str) -> String {
+    match patchfile {
+        "" => format!("chunk-{:04}", n),
+        _ => format!("chunk-{:04}+{}", n, patchfile),
+    }
+}
+
+/// The commit a patch came from, written the way `git log` shows it: `git
+/// apply` skips it, and splitting the patch again finds the commit.
+fn commit_block(hash: Option<&str>, author: Option<&str>, message: Option<&str>) -> Option<String> {
+    let message = message?;
+    let mut block = String::new();
+    if let Some(hash) = hash {
+        block.push_str(&format!("commit {}\n", hash));
+    }
+    if let Some(author) = author {
+        block.push_str(&format!("Author: {}\n", author));
+    }
+    block.push('\n');
+    for line in message.lines() {
+        block.push_str(&format!("    {}\n", line));
+    }
+    block.push('\n');
+    Some(block)
+}
+
+/// The hash `git format-patch` puts on the first line of the mail when the
+/// commit isn't known.
+const NO_COMMIT: &str = "0000000000000000000000000000000000000000";
+
+/// The mail `git format-patch` wraps a patch in, up to the `---` line above
+/// the diff: the subject is the first paragraph of `message`, the body the
+/// rest of it. `git am` reads the author and date from there.
+fn mail_envelope(
+    hash: Option<&str>,
+    author: &str,
+    date: Option<DateTime<FixedOffset>>,
+    message: &str,
+) -> String {
+    let (subject, body) = message
+        .trim()
+        .split_once("\n\n")
+        .unwrap_or((message.trim(), ""));
+    let subject = subject.lines().map(str::trim).collect::<Vec<_>>().join(" ");
+    let body = body.trim_matches('\n');
+
+    let mut mail = format!(
+        "From {} Mon Sep 17 00:00:00 2001\n",
+        hash.unwrap_or(NO_COMMIT)
+    );
+    mail.push_str(&format!("From: {}\n", author));
+    if let Some(date) = date {
+        mail.push_str(&format!("Date: {}\n", date.to_rfc2822()));
+    }
+    mail.push_str(&format!("Subject: [PATCH] {}\n", subject));
+    if !(author.is_ascii() && message.is_ascii()) {
+        mail.push_str("MIME-Version: 1.0\n");
+        mail.push_str("Content-Type: text/plain; charset=UTF-8\n");
+        mail.push_str("Content-Transfer-Encoding: 8bit\n");
+    }
+    mail.push('\n');
+    if !body.is_empty() {
+        mail.push_str(body);
+        mail.push('\n');
+    }
+    mail.push_str("---\n");
+    mail
+}
+
+/// What a mail made by `--mailbox` is about when the patch came without its
+/// commit: what happened to which file.
+fn mail_subject(change: Change, old: Option<&str>, new: Option<&str>, path: &str) -> String {
+    match (old, new) {
+        (Some(old), Some(new)) if old != new && change == Change::Add => {
+            format!("Copy {} to {}", old, new)
+        }
+        (Some(old), Some(new)) if old != new => format!("Rename {} to {}", old, new),
+        _ => match change {
+            Change::Add => format!("Add {}", path),
+            Change::Delete => format!("Remove {}", path),
+            Change::Modify => format!("Update {}", path),
+        },
+    }
+}
+
+/// Turns the modes, object names and added or removed file lines of `header`
+/// around, for a patch undoing the one read. [`rewrite_header`] swaps the
+/// paths.
+fn reverse_header(header: &str) -> String {
+    let value = |key: &str| {
+        header
+            .lines()
+            .find_map(|l| l.trim_end_matches('\r').strip_prefix(key))
+            .map(str::to_string)
+    };
+    let (old_mode, new_mode) = (value("old mode "), value("new mode "));
+
+    header
+        .split_inclusive('\n')
+        .map(|line| {
+            let text = line.trim_end_matches(['\r', '\n']);
+            let eol = &line[text.len()..];
+            let text = if let Some(mode) = text.strip_prefix("new file mode ") {
+                format!("deleted file mode {}", mode)
+            } else if let Some(mode) = text.strip_prefix("deleted file mode ") {
+                format!("new file mode {}", mode)
+            } else if text.starts_with("old mode ")
+                && let Some(mode) = &new_mode
+            {
+                format!("old mode {}", mode)
+            } else if text.starts_with("new mode ")
+                && let Some(mode) = &old_mode
+            {
+                format!("new mode {}", mode)
+            } else if let Some(index) = text.strip_prefix("index ")
+                && let Some((old, new)) = index.split_once("..")
+            {
+                // The mode, if any, follows the new object name.
+                format!("index {}..{}", new.split(' ').next().unwrap_or(new), old)
+                    + &new[new.find(' ').unwrap_or(new.len())..]
+            } else {
+                text.to_string()
+            };
+            text + eol
+        })
+        .collect()
+}
+
+/// Swaps the sides of a patch body: added lines become removed ones and the
+/// other way around, and so do the ranges of the hunk headers. The removed
+/// lines of every change are put before the added ones, as diffs have them,
+/// each keeping the `\ No newline` marker that followed it.
+fn reverse_body(lines: Vec<String>) -> Vec<String> {
+    let mut out = Vec::with_capacity(lines.len());
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    // Whether the last change line went to `added`, if the last line was one.
+    let mut last_added = None;
+
+    for line in lines {
+        match line.chars().next() {
+            Some('+') => {
+                removed.push(format!("-{}", &line[1..]));
+                last_added = Some(false);
+            }
+            Some('-') => {
+                added.push(format!("+{}", &line[1..]));
+                last_added = Some(true);
+            }
+            Some('\\') if last_added == Some(true) => added.push(line),
+            Some('\\') if last_added == Some(false) => removed.push(line),
+            _ => {
+                out.append(&mut removed);
+                out.append(&mut added);
+                last_added = None;
+                match reverse_hunk_header(&line) {
+                    Some(header) => out.push(header),
+                    None => out.push(line),
+                }
+            }
+        }
+    }
+    out.append(&mut removed);
+    out.append(&mut added);
+    out
+}
+
+/// `@@ -a,b +c,d @@` as `@@ -c,d +a,b @@`, or `None` if `line` isn't a hunk
+/// header.
+fn reverse_hunk_header(line: &str) -> Option<String> {
+    parse_hunk_header(line)?;
+    let (old, new) = line.strip_prefix("@@ -")?.split_once('+')?;
+    let end = new
+        .find(|c: char| !(c.is_ascii_digit() || c == ','))
+        .unwrap_or(new.len());
+    let (new, rest) = new.split_at(end);
+    Some(format!("@@ -{} +{}{}", new, old.trim_end(), rest))
+}
+
+/// Rewrites the paths in the lines of `header` that name the diffed files to
+/// `old` and `new`, behind the given source and destination prefixes.
+fn rewrite_header(
+    header: &str,
+    old: Option<&str>,
+    new: Option<&str>,
+    (src, dst): (&str, &str),
+) -> String {
+    let old_path = old.map_or("/dev/null".to_string(), |p| format!("{}{}", src, p));
+    let new_path = new.map_or("/dev/null".to_string(), |p| format!("{}{}", dst, p));
+
+    header
+        .split_inclusive('\n')
+        .map(|line| {
+            let text = line.trim_end_matches(['\r', '\n']);
+            let eol = &line[text.len()..];
+            let text = if text.starts_with("diff --git ") {
+                // Both sides are named here, even for added and removed files.
+                format!(
+                    "diff --git {}{} {}{}",
+                    src,
+                    old.or(new).unwrap_or_default(),
+                    dst,
+                    new.or(old).unwrap_or_default()
+                )
+            } else if text.starts_with("Index: ") {
+                // svn's file line, without prefixes.
+                format!("Index: {}", new.or(old).unwrap_or_default())
+            } else if text.starts_with("--- ") {
+                format!("--- {}", old_path)
+            } else if text.starts_with("+++ ") {
+                format!("+++ {}", new_path)
+            } else if text.starts_with("Binary files ") && text.ends_with(" differ") {
+                format!("Binary files {} and {} differ", old_path, new_path)
+            } else if let Some(kind) = ["rename", "copy"]
+                .into_iter()
+                .find(|kind| text.starts_with(&format!("{} from ", kind)))
+            {
+                format!("{} from {}", kind, old.unwrap_or_default())
+            } else if let Some(kind) = ["rename", "copy"]
+                .into_iter()
+                .find(|kind| text.starts_with(&format!("{} to ", kind)))
+            {
+                format!("{} to {}", kind, new.unwrap_or_default())
+            } else {
+                text.to_string()
+            };
+            text + eol
+        })
+        .collect()
+}
+
+/// The patches of `patches` passing the path and date filters of `options`,
+/// sorted or merged into renames as asked, which [`split`] then skips and
+/// takes from. Also returns how many renames were merged.
+fn select<'a, T: Sized + Read + 'a>(
+    patches: impl Iterator<Item = Patch<T>> + 'a,
+    options: &'a SplitOptions,
+) -> (Box<dyn Iterator<Item = Body<T>> + 'a>, usize) {
+    // Patches dropped unread are fine: the parser skips whatever is left of
+    // their bodies when looking for the next one. Sorting can't stream them,
+    // it needs every patch of the input, bodies included, before the first
+    // one is written.
+    let patches =
+        patches.filter(|p| !should_skip_patch(p, &options.filter) && in_date_range(p, options));
+    let mut merged = 0;
+    let patches: Box<dyn Iterator<Item = Body<T>>> =
+        if options.sort.is_some() || options.merge_renames {
+            let mut owned: Vec<_> = patches.map(Patch::into_owned).collect();
+            if options.merge_renames {
+                merged = merge_renames(&mut owned);
+            }
+            if let Some(key) = options.sort {
+                sort_patches(&mut owned, key);
+            }
+            Box::new(owned.into_iter().map(Body::Buffered))
+        } else {
+            Box::new(patches.map(Body::Streamed))
+        };
+    (patches, merged)
+}
+
+/// Whether [`split`] reads every body through before writing anything of
+/// the patch: to match on it, cut its context, drop hunks and the like, or
+/// to leave it out if a line that isn't UTF-8 cuts it short.
+fn reads_body(options: &SplitOptions) -> bool {
+    options.encoding == Encoding::Utf8Strict
+        || options.content_filter.is_some()
+        || options.context.is_some()
+        || options.drop_whitespace_only
+        || options.reverse
+        || options.dedup.is_some()
+        || options.header_template.is_some()
+}
+
+/// What [`screen`] made of a patch.
+enum Screen {
+    /// Kept, less this many hunks that only changed whitespace.
+    Kept(usize),
+    /// Left out for only changing whitespace.
+    WhitespaceOnly,
+    /// Left out for having no line the content filter matches.
+    NoMatch,
+}
+
+/// Checks the body of `owned`, the patch of `file`, against the filters of
+/// `options` that look at it, dropping its whitespace-only hunks if asked to.
+/// Fails on patches `options.reverse` can't undo.
+fn screen(owned: &mut OwnedPatch, options: &SplitOptions, file: &str) -> anyhow::Result<Screen> {
+    let mut dropped = 0;
+    if options.drop_whitespace_only {
+        let hunks = owned.hunk_count();
+        dropped = owned.drop_whitespace_only_hunks();
+        if dropped > 0 && dropped == hunks {
+            return Ok(Screen::WhitespaceOnly);
+        }
+    }
+    if let Some(content) = &options.content_filter
+        && !content.is_match(owned.lines())
+    {
+        return Ok(Screen::NoMatch);
+    }
+    // Undoing a copy would take the contents of the copied file, and a binary
+    // patch its reverse half, which isn't parsed.
+    if options.reverse && owned.copy().is_some() {
+        return Err(anyhow::anyhow!("cannot reverse the copy to '{}'", file));
+    }
+    if options.reverse && owned.binary_payload().is_some() {
+        return Err(anyhow::anyhow!(
+            "cannot reverse the binary patch of '{}'",
+            file
+        ));
+    }
+    Ok(Screen::Kept(dropped))
+}
+
+/// The SHA-256 [`SplitOptions::dedup`] checks every patch of `handle` for, up
+/// to the first one [`split`] would fail on, without writing or logging
+/// anything. Inputs split side by side are checked against the digests of the
+/// inputs before them, read beforehand, rather than against whichever thread
+/// got to a patch first.
+pub fn digests<T: Sized + Read>(handle: T, options: &SplitOptions) -> HashSet<[u8; 32]> {
+    let parser = DiffParser::with_encoding(handle, options.encoding)
+        .keep_relative(options.keep_relative)
+        .lenient(options.lenient);
+    let mut digests = HashSet::new();
+
+    for body in select(parser, options)
+        .0
+        .skip(options.skip)
+        .take(options.max_patches.unwrap_or(usize::MAX))
+    {
+        let Some(file) = body.meta().display_name() else {
+            continue;
+        };
+        let mut owned = body.into_owned();
+        match screen(&mut owned, options, &file) {
+            Ok(Screen::Kept(_)) => {
+                digests.insert(patch_digest(&owned, options.normalize_whitespace));
+            }
+            Ok(Screen::WhitespaceOnly | Screen::NoMatch) => {}
+            Err(_) => break,
+        }
+    }
+
+    digests
+}
+
+/// Writes a line for every patch of `handle` that passes the filters of
+/// `options` to `out`, in the order and with the renames [`split`] would
+/// write them: `A`, `M` or `D` and the path for added, modified and removed
+/// files, `R` or `C` and `old -> new` for renames and copies. Nothing else is
+/// written. Returns how many patches were listed.
+pub fn list<T: Sized + Read, W: Write>(
+    handle: T,
+    options: &SplitOptions,
+    out: &mut W,
+) -> anyhow::Result<usize> {
+    let mut parser = DiffParser::with_encoding(handle, options.encoding)
+        .keep_relative(options.keep_relative)
+        .lenient(options.lenient);
+    let painter = Painter::new(options.color);
+    let mut count = 0;
+
+    let (patches, _) = select(parser.by_ref(), options);
+    for body in patches
+        .skip(options.skip)
+        .take(options.max_patches.unwrap_or(usize::MAX))
+    {
+        let meta = body.meta();
+        let (old, new) = (meta.old_path.as_deref(), meta.new_path.as_deref());
+        let (marker, color, path) = match (old, new) {
+            (None, Some(new)) => ("A", Color::Green, new.to_string()),
+            (Some(old), None) => ("D", Color::Red, old.to_string()),
+            // Copies are new files with an old path.
+            (Some(old), Some(new)) if meta.new_file => {
+                ("C", Color::Cyan, format!("{} -> {}", old, new))
+            }
+            (Some(old), Some(new)) if old != new => {
+                ("R", Color::Cyan, format!("{} -> {}", old, new))
+            }
+            _ => match meta.display_name() {
+                Some(path) => ("M", Color::Yellow, path),
+                None => continue,
+            },
+        };
+        let line = format!("{} {}", painter.paint(color, marker), path);
+        if options.content_filter.is_some() || options.drop_whitespace_only {
+            let owned = body.into_owned();
+            if options.drop_whitespace_only && owned.is_whitespace_only() {
+                continue;
+            }
+            if let Some(content) = &options.content_filter
+                && !content.is_match(owned.lines())
+            {
+                continue;
+            }
+        }
+
+        count += 1;
+        match options.encoding {
+            Encoding::Bytes => out.write_all(&Encoding::Bytes.encode(&line))?,
+            _ => out.write_all(line.as_bytes())?,
+        }
+        writeln!(out)?;
+    }
+
+    if let Some(line) = parser.invalid_line() {
+        if options.strict {
+            return Err(anyhow::anyhow!("line {} is not valid UTF-8", line));
+        }
+        log::warn!(
+            "stopped at line {}, which is not valid UTF-8. Pass --encoding to read the rest",
+            line
+        );
+    }
+
+    Ok(count)
+}
+
+/// Splits one input into the patches of its files, adding an entry for every
+/// file written to `manifest`. `patchfile` is the input's name, appended to
+/// the patches' names; pass an empty one to leave it out. Returns how many
+/// patches there were and how many of them were kept.
+pub fn split<T: Sized + Read, W: Extend<ManifestEntry>>(
+    handle: T,
+    options: &SplitOptions,
+    patchfile: &str,
+    manifest: &mut W,
+) -> anyhow::Result<SplitCount> {
+    split_with(
+        handle,
+        options,
+        patchfile,
+        manifest,
+        options.writer().as_mut(),
+    )
+}
+
+/// Like [`split`], but hands the files to `writer` rather than writing them
+/// below `options.output_dir` or into `options.archive`.
+pub fn split_with<T: Sized + Read, W: Extend<ManifestEntry>>(
+    handle: T,
+    options: &SplitOptions,
+    patchfile: &str,
+    manifest: &mut W,
+    writer: &mut dyn PatchWriter,
+) -> anyhow::Result<SplitCount> {
+    // Every patch would get a file of its own, leaving nothing to chunk.
+    if options.numbered && options.chunk_size.is_some() {
+        return Err(anyhow::anyhow!(
+            "patches can't be both numbered and written in chunks"
+        ));
+    }
+    let mut parser = DiffParser::with_encoding(handle, options.encoding)
+        .keep_relative(options.keep_relative)
+        .lenient(options.lenient);
+    let strip = Cell::new(1);
+    let mut check = None;
+    let writer: &mut dyn PatchWriter = match &options.worktree {
+        Some(dir) => check.insert(ApplyCheck {
+            inner: writer,
+            dir,
+            strip: &strip,
+            path: PathBuf::new(),
+            patch: Vec::new(),
+            failures: 0,
+        }),
+        None => writer,
+    };
+    let mut checksums;
+    let writer: &mut dyn PatchWriter = match &options.checksums {
+        Some(sums) => {
+            checksums = Checksums {
+                inner: writer,
+                sums,
+                path: PathBuf::new(),
+                hasher: Sha256::new(),
+            };
+            &mut checksums
+        }
+        None => writer,
+    };
+    let mut raw;
+    let writer: &mut dyn PatchWriter = match options.encoding {
+        Encoding::Bytes => {
+            raw = RawBytes(writer);
+            &mut raw
+        }
+        _ => writer,
+    };
+    let mut chunks = None;
+    let writer: &mut dyn PatchWriter = match options.chunk_size {
+        Some(_) => chunks.insert(Chunks {
+            inner: writer,
+            current: None,
+        }),
+        None => writer,
+    };
+    // Paths read as bytes are turned back into text where they're shown or
+    // used as names.
+    let text = |path: String| match options.encoding {
+        Encoding::Bytes => String::from_utf8_lossy(&Encoding::Bytes.encode(&path)).into_owned(),
+        _ => path,
+    };
+    let mut inconsistent = 0;
+    // Every patch read, and those kept.
+    let mut seen = 0;
+    let mut count = 0;
+    // Patches and hunks of other patches left out for only changing whitespace.
+    let mut whitespace_only = (0, 0);
+    // Patches left out for being the same as one written before.
+    let mut duplicates = 0;
+    // Patches whose header names their file twice, filtered out or not.
+    let mut repeated_file_lines = 0;
+    // How often each name came up and for which file first, e.g. for a file
+    // changed by several commits of a `git log -p`.
+    let names = options.names.clone().unwrap_or_default();
+
+    let patches = parser.by_ref().inspect(|p| {
+        seen += 1;
+        if let Some(line) = p.repeated_file_line() {
+            repeated_file_lines += 1;
+            log::warn!(
+                "{}: the header has more than one '{}' line, the last one names the file",
+                p.display_name().unwrap_or_default(),
+                &line[..3]
+            );
+        }
+    });
+    let (patches, merged) = select(patches, options);
+    if options.merge_renames {
+        log::debug!("Merged {} removed and added files into renames", merged);
+    }
+
+    patches
+        .skip(options.skip)
+        .take(options.max_patches.unwrap_or(usize::MAX))
+        .try_for_each(|body| {
+            let meta = body.meta();
+            let Some(file) = meta.display_name() else {
+                log::warn!("skipped a patch with /dev/null on both sides");
+                return Ok(());
+            };
+            let file = text(file);
+            let (old_path, new_path) = (meta.old_path.as_deref(), meta.new_path.as_deref());
+            let change = match (old_path, new_path) {
+                (None, _) if options.reverse => Change::Delete,
+                (None, _) => Change::Add,
+                // Copies are new files with an old path.
+                _ if meta.new_file => Change::Add,
+                (_, None) if options.reverse => Change::Add,
+                (_, None) => Change::Delete,
+                _ => Change::Modify,
+            };
+            // Only fails the split if the patch isn't filtered out.
+            let old = strip_components(old_path, options.strip);
+            let new = strip_components(new_path, options.strip);
+            let named = options.name_from.pick(old_path, new_path).map(|p| text(p.to_string()));
+            let path = strip_components(named.as_deref(), options.strip);
+            let mut header = meta.header;
+            let commit = match options.include_commit_headers {
+                true => body.commit_block(),
+                false => None,
+            };
+            let prefixes = match body.has_prefixes() {
+                true => ("a/", "b/"),
+                false => ("", ""),
+            };
+
+            let body = if reads_body(options) {
+                let mut owned = body.into_owned();
+                if owned.is_cut_short() {
+                    log::warn!(
+                        "{}: left out the patch, it's cut short by a line that isn't UTF-8",
+                        file
+                    );
+                    return Ok(());
+                }
+                match screen(&mut owned, options, &file)? {
+                    Screen::Kept(dropped) => whitespace_only.1 += dropped,
+                    Screen::WhitespaceOnly => {
+                        whitespace_only.0 += 1;
+                        return Ok(());
+                    }
+                    Screen::NoMatch => return Ok(()),
+                }
+                if let Some(seen) = &options.dedup
+                    && !seen
+                        .lock()
+                        .unwrap()
+                        .insert(patch_digest(&owned, options.normalize_whitespace))
+                {
+                    duplicates += 1;
+                    return Ok(());
+                }
+                Body::Buffered(owned)
+            } else {
+                body
+            };
+
+            count += 1;
+            let (old, new) = (old?, new?);
+            let path = path?.unwrap_or_default();
+            let mut name = match options.chunk_size {
+                Some(size) => chunk_name((count - 1) / size + 1, patchfile),
+                None => output_name(&path, options, patchfile),
+            };
+            if options.numbered {
+                name = match name.rsplit_once('/') {
+                    Some((dir, file)) => format!("{}/{:04}-{}", dir, count, file),
+                    None => format!("{:04}-{}", count, name),
+                };
+            }
+            // Paths kept as they are, or absolute ones, could lead anywhere.
+            if Path::new(&name)
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(anyhow::anyhow!(
+                    "the patch of {} would be written outside the output directory, as '{}'",
+                    file,
+                    name
+                ));
+            }
+            let (seen, first) = {
+                let mut names = names.lock().unwrap();
+                let (seen, first) = names.entry(name.clone()).or_insert((0, file.clone()));
+                *seen += 1;
+                (*seen, first.clone())
+            };
+            // Chunks hold several patches by design.
+            if seen > 1 && options.chunk_size.is_none() {
+                match options.on_collision {
+                    OnCollision::Error => {
+                        return Err(anyhow::anyhow!(
+                            "the patches of {} and {} would both be written to '{}'",
+                            first,
+                            file,
+                            name
+                        ));
+                    }
+                    OnCollision::Number => name = format!("{}~{}", name, seen),
+                    OnCollision::Overwrite => {}
+                }
+            }
+            let (old, new) = match options.reverse {
+                true => (new, old),
+                false => (old, new),
+            };
+            if options.reverse
+                || options.strip > 0
+                || options.src_prefix.is_some()
+                || options.dst_prefix.is_some()
+            {
+                let prefixes = (
+                    options.src_prefix.as_deref().unwrap_or(prefixes.0),
+                    options.dst_prefix.as_deref().unwrap_or(prefixes.1),
+                );
+                header = rewrite_header(&header, old.as_deref(), new.as_deref(), prefixes);
+            }
+            let src_prefix = options.src_prefix.as_deref().unwrap_or(prefixes.0);
+            strip.set(src_prefix.matches('/').count());
+            if options.reverse {
+                header = reverse_header(&header);
+            }
+            if let (Some(template), Body::Buffered(owned)) = (&options.header_template, &body) {
+                let (mut added, mut removed) =
+                    (count_lines(owned.lines(), '+'), count_lines(owned.lines(), '-'));
+                if options.reverse {
+                    (added, removed) = (removed, added);
+                }
+                header = template.render(&header, &path, added, removed);
+            }
+            if let Some(commit) = commit {
+                header = commit + &header;
+            }
+            // Whatever comes between `---` and the diff, `git am` skips.
+            header = match &options.header_prefix {
+                Some(prefix) if prefix.ends_with('\n') => format!("{}{}", prefix, header),
+                Some(prefix) => format!("{}\n{}", prefix, header),
+                None => header,
+            };
+            if options.mailbox {
+                let subject = mail_subject(change, old.as_deref(), new.as_deref(), &path);
+                let author = options.mail_author.as_deref();
+                let mail = body.mail_envelope(author, &subject).ok_or_else(|| {
+                    anyhow::anyhow!("no author for the mail of '{}', which came without its commit", file)
+                })?;
+                header = mail + &header;
+            }
+
+            let trimmed = Cell::new(0);
+            let normalize = |mut line: String| {
+                if options.normalize_whitespace && trim_added_line(&mut line) {
+                    trimmed.set(trimmed.get() + 1);
+                }
+                line
+            };
+
+            let mut written = Vec::new();
+            let (checks, malformed) = match body {
+                Body::Buffered(owned) => {
+                    if options.write || options.worktree.is_some() {
+                        let lines = match options.context {
+                            Some(n) => {
+                                let mut kept = Vec::new();
+                                let lines =
+                                    reduce_context(owned.lines(), owned.hunks(), n, &mut kept);
+                                for hunk in kept {
+                                    log::warn!(
+                                        "{}: left hunk '{}' as it was, cutting its context to {} lines would split it",
+                                        file, hunk, n
+                                    );
+                                }
+                                lines
+                            }
+                            None => owned.lines().to_vec(),
+                        };
+                        let lines = match options.reverse {
+                            true => reverse_body(lines),
+                            false => lines,
+                        };
+                        written = write_patch(
+                            name,
+                            &header,
+                            owned.binary_payload(),
+                            owned.is_binary(),
+                            lines.into_iter().map(&normalize),
+                            options,
+                            writer,
+                        )?;
+                    }
+                    (owned.hunks().to_vec(), owned.malformed_hunk().map(str::to_string))
+                }
+                Body::Streamed(mut patch) if options.write || options.worktree.is_some() => {
+                    let payload = patch.binary_payload().map(str::to_string);
+                    written = write_patch(
+                        name,
+                        &header,
+                        payload.as_deref(),
+                        patch.is_binary(),
+                        patch.lines().map(&normalize),
+                        options,
+                        writer,
+                    )?;
+                    (patch.hunks().to_vec(), patch.malformed_hunk().map(str::to_string))
+                }
+                Body::Streamed(mut patch) => {
+                    patch.lines().for_each(drop);
+                    (patch.hunks().to_vec(), patch.malformed_hunk().map(str::to_string))
+                }
+            };
+
+            if let Some(line) = malformed {
+                log::warn!(
+                    "{}: couldn't parse hunk header '{}', left out the rest of the patch",
+                    file,
+                    line
+                );
+            }
+
+            if trimmed.get() > 0 {
+                log::warn!(
+                    "{}: trimmed trailing whitespace off {} added lines",
+                    file,
+                    trimmed.get()
+                );
+            }
+
+            // Patches only written for `git apply --check` weren't kept.
+            if options.write {
+                for output in &written {
+                    log::debug!("Wrote {}", output.display());
+                }
+                manifest.extend(written.into_iter().map(|output| ManifestEntry {
+                    source: file.clone(),
+                    output,
+                    change,
+                }));
+            }
+
+            if options.verify {
+                for hunk in checks.iter().filter(|h| !h.is_consistent()) {
+                    inconsistent += 1;
+                    log::error!(
+                        "{}: hunk '{}' declares -{} +{} lines but has -{} +{}{}",
+                        file,
+                        hunk.header(),
+                        hunk.old_len(),
+                        hunk.new_len(),
+                        hunk.body_len().0,
+                        hunk.body_len().1,
+                        if hunk.has_trailing_lines() {
+                            " followed by stray body lines"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+            }
+
+            // Without a known input size the spinner counts patches instead.
+            if let Some(bar) = &options.progress
+                && bar.length().is_none()
+            {
+                bar.inc(1);
+            }
+
+            Ok::<_, anyhow::Error>(())
+        })?;
+
+    if let Some(chunks) = &mut chunks {
+        chunks.close()?;
+    }
+
+    if options.drop_whitespace_only {
+        log::debug!(
+            "Dropped {} whitespace-only patches and {} whitespace-only hunks of other patches",
+            whitespace_only.0,
+            whitespace_only.1
+        );
+    }
+
+    if duplicates > 0 {
+        log::info!("Skipped {} duplicate patches", duplicates);
+    }
+
+    if let Some(line) = parser.invalid_line() {
+        if options.strict {
+            return Err(anyhow::anyhow!("line {} is not valid UTF-8", line));
+        }
+        log::warn!(
+            "stopped at line {}, which is not valid UTF-8. Pass --encoding to read the rest",
+            line
+        );
+    }
+
+    if options.strict && repeated_file_lines > 0 {
+        return Err(anyhow::anyhow!(
+            "{} patches name their file more than once",
+            repeated_file_lines
+        ));
+    }
+
+    if options.strict {
+        // Whatever `max_patches` left unread still leads to the end.
+        parser.by_ref().for_each(drop);
+        if let Some(hunk) = parser.truncated_hunk() {
+            return Err(anyhow::anyhow!(
+                "the input ends in the middle of hunk '{}', which declares -{} +{} lines but has -{} +{}. It looks truncated",
+                hunk.header(),
+                hunk.old_len(),
+                hunk.new_len(),
+                hunk.body_len().0,
+                hunk.body_len().1
+            ));
+        }
+    }
+
+    if inconsistent > 0 {
+        return Err(anyhow::anyhow!("{} inconsistent hunks found", inconsistent));
+    }
+
+    if let Some(check) = check
+        && check.failures > 0
+    {
+        return Err(anyhow::anyhow!(
+            "{} patches don't apply to {}",
+            check.failures,
+            check.dir.display()
+        ));
+    }
+
+    Ok(SplitCount { seen, kept: count })
+}
+
+/// Joins the patches in `files`, in the given order, into one diff written to
+/// `out`: the inverse of [`split`]. Anything around the patches, like a
+/// [`SplitOptions::header_prefix`], is left out. Every file has to hold at
+/// least one patch, and every hunk has to match its header. Returns how many
+/// patches were written.
+pub fn combine<W: Write>(files: &[PathBuf], out: &mut W) -> anyhow::Result<usize> {
+    let mut count = 0;
+
+    for path in files {
+        let mut found = false;
+        for mut patch in DiffParser::new(File::open(path)?) {
+            found = true;
+            out.write_all(patch.header().as_bytes())?;
+            if let Some(payload) = patch.binary_payload() {
+                out.write_all(payload.as_bytes())?;
+            }
+            for line in patch.lines() {
+                out.write_all(line.as_bytes())?;
+                out.write_all(b"\n")?;
+            }
+
+            if let Some(hunk) = patch.hunks().iter().find(|h| !h.is_consistent()) {
+                return Err(anyhow::anyhow!(
+                    "{}: hunk '{}' doesn't match its line counts",
+                    path.display(),
+                    hunk.header()
+                ));
+            }
+            count += 1;
+        }
+
+        if !found {
+            return Err(anyhow::anyhow!("{} holds no patch", path.display()));
+        }
+    }
+
+    Ok(count)
+}
+
+/// Writes a patch under `name` as `options` asks for: as a patch, one patch
+/// per hunk, or as the extracted file contents. `binary` and `payload` are
+/// what the patch's `is_binary()` and `binary_payload()` return, `lines` its
+/// body. Returns the paths of the files handed to `out`.
+fn write_patch(
+    name: String,
+    header: &str,
+    payload: Option<&str>,
+    binary: bool,
+    lines: impl Iterator<Item = String>,
+    options: &SplitOptions,
+    out: &mut dyn PatchWriter,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let filter = &options.filter;
+    let ending = options.line_ending;
+    let patch_path = |name: String| with_extension(name, &options.extension);
+
+    if let FilterType::OnlyNew(FileProcessing::ExtractFile(mode))
+    | FilterType::OnlyRemoved(FileProcessing::ExtractFile(mode)) = filter
+    {
+        let f = PathBuf::from(name);
+        if binary {
+            // Only a `GIT binary patch` literal carries the file itself.
+            let old = matches!(mode, ExtractMode::OldSide);
+            let contents = match payload {
+                Some(payload) => decode_binary_payload(payload, old).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "cannot extract the contents of binary file '{}' from its GIT binary patch",
+                        f.display()
+                    )
+                })?,
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "cannot extract the contents of binary file '{}', the diff only says they differ. Make it with git diff --binary",
+                        f.display()
+                    ));
+                }
+            };
+            out.begin(&f)?;
+            out.write_bytes(&contents)?;
+            out.finish()?;
+            return Ok(vec![f]);
+        }
+
+        out.begin(&f)?;
+        let complete = write_side(out, lines, mode, ending)?;
+        out.finish()?;
+        if !complete {
+            log::warn!(
+                "the diff for '{}' doesn't cover the whole file, the extracted contents are partial",
+                f.display()
+            );
+        }
+        return Ok(vec![f]);
+    }
+
+    // Mails end in a signature, like those of `git format-patch`.
+    let finish = |out: &mut dyn PatchWriter| {
+        if options.mailbox {
+            let signature = format!("-- \nspatch {}\n\n", env!("CARGO_PKG_VERSION"));
+            write_text(out, &signature, ending)?;
+        }
+        out.finish()
+    };
+    let mut written = Vec::new();
+
+    if options.split_by_hunk && !binary {
+        // One file per hunk, each carrying its own copy of the header.
+        for line in lines {
+            if line.starts_with("@@ -") {
+                if !written.is_empty() {
+                    finish(out)?;
+                }
+                let path = patch_path(format!("{}#{}", name, written.len() + 1));
+                out.begin(&path)?;
+                written.push(path);
+                write_text(out, header, ending)?;
+            }
+            if !written.is_empty() {
+                write_line(out, &line, ending)?;
+            }
+        }
+
+        if !written.is_empty() {
+            finish(out)?;
+        } else {
+            // Hunk-less patches, e.g. mode changes, are still written whole.
+            let path = patch_path(name);
+            out.begin(&path)?;
+            write_text(out, header, ending)?;
+            finish(out)?;
+            written.push(path);
+        }
+        return Ok(written);
+    }
+
+    let path = patch_path(name);
+    out.begin(&path)?;
+    written.push(path);
+    write_text(out, header, ending)?;
+
+    // Binary diffs have no hunks, at most an encoded payload.
+    if binary {
+        write_text(out, payload.unwrap_or_default(), ending)?;
+    } else {
+        for line in lines {
+            write_line(out, &line, ending)?;
+        }
+    }
+
+    finish(out)?;
+    Ok(written)
+}
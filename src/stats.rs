@@ -0,0 +1,134 @@
+use crate::color::{Color, Painter};
+use crate::diff_parser::{DiffParser, PatchMeta};
+use std::io::{self, Read, Write};
+
+/// Columns the `+`/`-` graph may take before it's scaled down.
+const GRAPH_WIDTH: usize = 50;
+
+/// How many lines a single patch adds to and removes from its file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileStat {
+    /// The diffed file, or `old => new` for renames and copies.
+    pub path: String,
+    pub added: usize,
+    pub removed: usize,
+    /// Binary patches have no lines to count.
+    pub binary: bool,
+}
+
+/// Everything a diffstat needs to know about a whole input: what every patch
+/// is about, how many lines it adds and removes, and the totals.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffStat {
+    pub patches: Vec<PatchMeta>,
+    /// The line counts of [`Self::patches`], in the same order.
+    pub files: Vec<FileStat>,
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub binary_files: usize,
+}
+
+/// Reads every patch of `parser` through, counting the lines of each body as
+/// it goes. Fails if the parse stopped at a line that isn't UTF-8.
+pub fn diff_stat<T: Sized + Read>(mut parser: DiffParser<T>) -> anyhow::Result<DiffStat> {
+    let mut patches = Vec::new();
+    let mut files = Vec::new();
+    for mut patch in parser.by_ref() {
+        patches.push(patch.meta());
+        let path = match (patch.old_path(), patch.new_path()) {
+            (Some(old), Some(new)) if old != new => format!("{} => {}", old, new),
+            _ => patch.display_name().unwrap_or_default(),
+        };
+        let (mut added, mut removed) = (0, 0);
+        // The body has to be read now, before the parser moves on and drops
+        // whatever is left of it.
+        for line in patch.lines().filter(|l| !l.starts_with("@@ -")) {
+            match line.chars().next() {
+                Some('+') => added += 1,
+                Some('-') => removed += 1,
+                _ => {}
+            }
+        }
+        files.push(FileStat {
+            path,
+            added,
+            removed,
+            binary: patch.is_binary(),
+        });
+    }
+
+    if let Some(line) = parser.invalid_line() {
+        log::warn!(
+            "stopped at line {}, which is not valid UTF-8, the rest isn't counted",
+            line
+        );
+    }
+
+    Ok(DiffStat {
+        files_changed: files.len(),
+        insertions: files.iter().map(|f| f.added).sum(),
+        deletions: files.iter().map(|f| f.removed).sum(),
+        binary_files: files.iter().filter(|f| f.binary).count(),
+        patches,
+        files,
+    })
+}
+
+/// Counts the lines of every patch read from `handle`, in order.
+pub fn collect<T: Sized + Read>(handle: T) -> anyhow::Result<Vec<FileStat>> {
+    Ok(diff_stat(DiffParser::new(handle))?.files)
+}
+
+/// Writes `stats` the way `git diff --stat` does, one line per file and a
+/// summary at the end.
+pub fn write<W: Write>(stats: &[FileStat], out: &mut W) -> io::Result<()> {
+    write_with(stats, Painter::default(), out)
+}
+
+/// Like [`write`], coloring the graph and the summary's counts with `painter`,
+/// insertions green and deletions red.
+pub fn write_with<W: Write>(stats: &[FileStat], painter: Painter, out: &mut W) -> io::Result<()> {
+    let path_width = stats.iter().map(|s| s.path.chars().count()).max();
+    let path_width = path_width.unwrap_or_default();
+    let most = stats.iter().map(|s| s.added + s.removed).max();
+    let most = most.unwrap_or_default();
+    let count_width = most.to_string().len();
+    // Files with few changes keep at least one column for each kind.
+    let scale = |n: usize| match n {
+        0 => 0,
+        _ if most <= GRAPH_WIDTH => n,
+        _ => (n * GRAPH_WIDTH / most).max(1),
+    };
+
+    for stat in stats {
+        if stat.binary {
+            writeln!(out, " {:<path_width$} | Bin", stat.path)?;
+        } else {
+            let (added, removed) = (scale(stat.added), scale(stat.removed));
+            writeln!(
+                out,
+                " {:<path_width$} | {:>count_width$}{}{}{}",
+                stat.path,
+                stat.added + stat.removed,
+                if added + removed == 0 { "" } else { " " },
+                painter.paint(Color::Green, &"+".repeat(added)),
+                painter.paint(Color::Red, &"-".repeat(removed)),
+            )?;
+        }
+    }
+
+    let added: usize = stats.iter().map(|s| s.added).sum();
+    let removed: usize = stats.iter().map(|s| s.removed).sum();
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    write!(out, " {} file{} changed", stats.len(), plural(stats.len()))?;
+    if added > 0 || removed == 0 {
+        let text = format!("{} insertion{}(+)", added, plural(added));
+        write!(out, ", {}", painter.paint(Color::Green, &text))?;
+    }
+    if removed > 0 || added == 0 {
+        let text = format!("{} deletion{}(-)", removed, plural(removed));
+        write!(out, ", {}", painter.paint(Color::Red, &text))?;
+    }
+    writeln!(out)
+}
@@ -0,0 +1,2394 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn test_patch_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("test_patches")
+        .join(format!("{}.patch", name))
+}
+
+/// Runs spatch with `args`, feeding `stdin` (if any) to it.
+fn spatch(args: &[&str], stdin: Option<&Path>) -> anyhow::Result<Output> {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_spatch"));
+    cmd.args(args);
+    cmd.stdin(match stdin {
+        Some(p) => Stdio::from(fs::File::open(p)?),
+        None => Stdio::null(),
+    });
+    Ok(cmd.output()?)
+}
+
+/// Runs spatch with `args` and a terminal for stdin, as when it's run by hand
+/// without piping anything.
+#[cfg(unix)]
+fn spatch_on_tty(args: &[&str]) -> anyhow::Result<Output> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    let (mut leader, mut follower) = (0, 0);
+    let (name, termp, winp) = (std::ptr::null_mut(), std::ptr::null(), std::ptr::null());
+    // SAFETY: openpty only writes the two descriptors, which are then owned
+    // here and closed on drop.
+    let (_leader, follower) = unsafe {
+        if libc::openpty(&mut leader, &mut follower, name, termp, winp) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        (OwnedFd::from_raw_fd(leader), OwnedFd::from_raw_fd(follower))
+    };
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_spatch"));
+    cmd.args(args).stdin(Stdio::from(follower));
+    Ok(cmd.output()?)
+}
+
+/// Sorted names of the files directly inside `dir`.
+fn file_names(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut names = fs::read_dir(dir)?
+        .map(|e| Ok(e?.file_name().to_string_lossy().into_owned()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    names.sort();
+    Ok(names)
+}
+
+#[test]
+fn test_extension_is_not_doubled() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir], Some(&test_patch_path("patch_of_patches")))?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        [
+            "patches-add-feature-xyz.patch",
+            "patches-fix-bug-123.patch",
+            "patches-update-readme.patch",
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_custom_and_empty_extension() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(
+        &["-o", dir, "--extension", "diff"],
+        Some(&test_patch_path("multi")),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["one.diff", "two.diff"]);
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(
+        &["-o", dir, "--extension", ""],
+        Some(&test_patch_path("multi")),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["one", "two"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_line_endings() -> anyhow::Result<()> {
+    let input = test_patch_path("crlf");
+    let original = fs::read_to_string(&input)?;
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    assert!(spatch(&["-o", dir], Some(&input))?.status.success());
+    let combined = fs::read_to_string(out.path().join("win.txt.patch"))?
+        + &fs::read_to_string(out.path().join("pic.png.patch"))?;
+    assert_eq!(combined, original);
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--line-ending", "lf"], Some(&input))?;
+    assert!(res.status.success());
+    let text = fs::read_to_string(out.path().join("win.txt.patch"))?;
+    assert!(!text.contains('\r'));
+    assert!(text.ends_with(" first\n-old\n+new\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_split_by_hunk() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(
+        &["-o", dir, "--split-by-hunk"],
+        Some(&test_patch_path("multi_hunk")),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        ["README#1.patch", "src-lib.rs#1.patch", "src-lib.rs#2.patch"]
+    );
+
+    let second = fs::read_to_string(out.path().join("src-lib.rs#2.patch"))?;
+    assert!(second.starts_with("diff --git a/src/lib.rs b/src/lib.rs\n"));
+    assert!(second.contains("+++ b/src/lib.rs\n@@ -10,3 +10,4 @@ fn two() {\n"));
+    assert!(!second.contains("fn one()"));
+
+    Ok(())
+}
+
+#[test]
+fn test_verify() -> anyhow::Result<()> {
+    let cwd = tempfile::tempdir()?;
+    let res = Command::new(env!("CARGO_BIN_EXE_spatch"))
+        .current_dir(cwd.path())
+        .args(["--verify", "--files"])
+        .arg(test_patch_path("mismatch"))
+        .output()?;
+    assert!(!res.status.success());
+    let stderr = String::from_utf8(res.stderr)?;
+    assert!(stderr.contains("x: hunk '@@ -1,1 +1,1 @@' declares -1 +1 lines but has -0 +1"));
+    // --verify without -o only checks.
+    assert!(file_names(cwd.path())?.is_empty());
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(
+        &["--verify", "-o", dir],
+        Some(&test_patch_path("multi_hunk")),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        ["README.patch", "src-lib.rs.patch"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_preserve_dirs() -> anyhow::Result<()> {
+    let input = test_patch_path("patch_of_patches");
+
+    // Extracted files are flattened just like patches unless asked otherwise.
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    assert!(
+        spatch(&["-o", dir, "-n", "-x"], Some(&input))?
+            .status
+            .success()
+    );
+    assert_eq!(
+        file_names(out.path())?,
+        ["patches-add-feature-xyz.patch", "patches-fix-bug-123.patch"]
+    );
+
+    for args in [&["-n", "-x"][..], &["-n"]] {
+        let out = tempfile::tempdir()?;
+        let dir = out.path().to_str().unwrap();
+        let res = spatch(
+            &[&["-o", dir, "--preserve-dirs"], args].concat(),
+            Some(&input),
+        )?;
+        assert!(res.status.success());
+        assert_eq!(file_names(out.path())?, ["patches"]);
+        assert_eq!(
+            file_names(&out.path().join("patches"))?,
+            ["add-feature-xyz.patch", "fix-bug-123.patch"]
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_group_by_dir() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(
+        &["-o", dir, "--group-by-dir"],
+        Some(&test_patch_path("nested_dirs")),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["Makefile.patch", "docs", "src"]);
+    assert_eq!(file_names(&out.path().join("docs"))?, ["guide.md.patch"]);
+    assert_eq!(
+        file_names(&out.path().join("src"))?,
+        ["main.rs.patch", "parser-lexer.rs.patch"]
+    );
+
+    let res = spatch(
+        &["-o", dir, "--group-by-dir", "--preserve-dirs"],
+        Some(&test_patch_path("nested_dirs")),
+    )?;
+    assert!(!res.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_output_prefix_and_suffix() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(
+        &[
+            "-o",
+            dir,
+            "--group-by-dir",
+            "--output-prefix",
+            "hotfix-",
+            "--output-suffix",
+            "-v2",
+        ],
+        Some(&test_patch_path("nested_dirs")),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        ["docs", "hotfix-Makefile-v2.patch", "src"]
+    );
+    assert_eq!(
+        file_names(&out.path().join("src"))?,
+        ["hotfix-main.rs-v2.patch", "hotfix-parser-lexer.rs-v2.patch"]
+    );
+
+    // Repeated names are numbered after the suffix.
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(
+        &["-o", dir, "--output-suffix", "-v2"],
+        Some(&test_patch_path("git_log")),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        [
+            "one.txt-v2.patch",
+            "one.txt-v2~2.patch",
+            "two.txt-v2.patch",
+            "two.txt-v2~2.patch"
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_manifest() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().join("patches");
+    fs::create_dir(&dir)?;
+    let manifest = out.path().join("manifest.tsv");
+
+    let res = spatch(
+        &[
+            "-o",
+            dir.to_str().unwrap(),
+            "--manifest",
+            manifest.to_str().unwrap(),
+        ],
+        Some(&test_patch_path("git_binary_patch")),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        fs::read_to_string(&manifest)?,
+        "path\toutput\tchange\n\
+         blob.bin\tblob.bin.patch\tmodify\n\
+         keep.txt\tkeep.txt.patch\tmodify\n\
+         new.bin\tnew.bin.patch\tadd\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_jobs() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    // Splits `inputs` with `-j 1` and `-j 3`, returning the names and contents
+    // of the files written and the manifest of each run.
+    let split = |inputs: &[String], args: &[&str]| -> anyhow::Result<Vec<_>> {
+        let mut runs = Vec::new();
+        for jobs in ["1", "3"] {
+            let run = tempfile::tempdir_in(out.path())?;
+            let dir = run.path().join("out");
+            let manifest = run.path().join("manifest.tsv");
+            let mut all = vec![
+                "-o",
+                dir.to_str().unwrap(),
+                "--manifest",
+                manifest.to_str().unwrap(),
+                "-j",
+                jobs,
+            ];
+            all.extend(args);
+            all.push("--files");
+            all.extend(inputs.iter().map(String::as_str));
+            let res = spatch(&all, None)?;
+            assert!(res.status.success());
+
+            let names = file_names(&dir)?;
+            let contents = names
+                .iter()
+                .map(|name| fs::read(dir.join(name)))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            runs.push((names, contents, fs::read_to_string(&manifest)?));
+        }
+        Ok(runs)
+    };
+
+    // Whichever thread splits an input, the files and the manifest come out
+    // as they do on one.
+    let inputs: Vec<_> = ["multi", "naming", "nested_dirs", "git_log", "svn", "copy"]
+        .into_iter()
+        .map(|name| test_patch_path(name).to_str().unwrap().to_string())
+        .collect();
+    let runs = split(&inputs, &[])?;
+    assert!(runs[0].0.len() > inputs.len());
+    // The manifest lists the inputs in the order they were given.
+    let manifest = &runs[0].2;
+    let first = |output: &str| manifest.find(output).unwrap();
+    assert!(first("+multi.patch") < first("+naming.patch"));
+    assert!(first("+svn.patch") < first("+copy.patch"));
+    assert_eq!(runs[0], runs[1]);
+
+    // A patch several inputs share is written for the first of them, however
+    // long that one takes to get to it.
+    let dir = tempfile::tempdir()?;
+    let duplicates = fs::read_to_string(test_patch_path("duplicates"))?;
+    let mut slow = String::new();
+    for i in 0..5000 {
+        slow += &format!(
+            "diff --git a/f{i} b/f{i}\n--- a/f{i}\n+++ b/f{i}\n@@ -1 +1 @@\n-{i}\n+{i}!\n"
+        );
+    }
+    let inputs: Vec<_> = [
+        ("slow", slow + &duplicates),
+        ("fast", duplicates.clone()),
+        ("faster", duplicates),
+    ]
+    .into_iter()
+    .map(|(name, text)| {
+        let path = dir.path().join(format!("{}.patch", name));
+        fs::write(&path, text)?;
+        Ok(path.to_str().unwrap().to_string())
+    })
+    .collect::<anyhow::Result<_>>()?;
+    let runs = split(&inputs, &["--dedup"])?;
+    assert_eq!(runs[0].0.len(), 5000 + 5);
+    assert!(runs[0].0.iter().all(|name| name.contains("+slow")));
+    assert_eq!(runs[0], runs[1]);
+
+    Ok(())
+}
+
+#[test]
+fn test_extract_binary() -> anyhow::Result<()> {
+    let input = test_patch_path("binary_literal");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "-n", "-x"], Some(&input))?;
+    assert!(res.status.success());
+    let all_bytes: Vec<u8> = (0..=255).chain(0..=255).collect();
+    assert_eq!(fs::read(out.path().join("added.bin"))?, all_bytes);
+
+    let res = spatch(
+        &["-o", dir, "-r", "-x", "--line-ending", "crlf"],
+        Some(&input),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        fs::read(out.path().join("doomed.bin"))?,
+        b"\x00\x01\x02old binary\xff\xfe"
+    );
+
+    // A plain git diff doesn't carry the contents at all.
+    let res = spatch(
+        &["-o", dir, "-n", "-x"],
+        Some(&test_patch_path("binary_simple")),
+    )?;
+    assert!(!res.status.success());
+    assert!(String::from_utf8(res.stderr)?.contains("git diff --binary"));
+
+    Ok(())
+}
+
+#[test]
+fn test_worktree() -> anyhow::Result<()> {
+    // Two binary files, left unchecked, and keep.txt gaining a line.
+    let input = test_patch_path("binary_literal");
+    let tree = tempfile::tempdir()?;
+    let worktree = tree.path().to_str().unwrap();
+    fs::write(tree.path().join("keep.txt"), "text\n")?;
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    for args in [
+        &["--worktree", worktree][..],
+        &["--worktree", worktree, "--no-prefix"],
+    ] {
+        let res = spatch(&[&["-o", dir], args].concat(), Some(&input))?;
+        assert!(res.status.success());
+        let stderr = String::from_utf8(res.stderr)?;
+        assert!(stderr.contains("added.bin.patch: binary, not checked"));
+    }
+    assert_eq!(file_names(out.path())?.len(), 3);
+    // Checking nothing but the worktree writes nothing.
+    let res = spatch(&["--worktree", worktree], Some(&input))?;
+    assert!(res.status.success());
+    assert!(!Path::new("keep.txt.patch").exists());
+
+    fs::write(tree.path().join("keep.txt"), "other\n")?;
+    let res = spatch(&["--worktree", worktree], Some(&input))?;
+    assert!(!res.status.success());
+    let stderr = String::from_utf8(res.stderr)?;
+    assert!(stderr.contains("keep.txt.patch doesn't apply to"));
+    assert!(stderr.contains("1 patches don't apply"));
+
+    Ok(())
+}
+
+#[test]
+fn test_strip() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(
+        &["-o", dir, "--strip", "1"],
+        Some(&test_patch_path("no_prefix")),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["file.txt.patch", "x.rs.patch"]);
+    let text = fs::read_to_string(out.path().join("file.txt.patch"))?;
+    assert!(text.starts_with("diff --git file.txt file.txt\n"));
+    assert!(text.contains("\n--- file.txt\n+++ file.txt\n"));
+
+    // README has no directory to strip.
+    let res = spatch(
+        &["-o", dir, "--strip", "1"],
+        Some(&test_patch_path("multi_hunk")),
+    )?;
+    assert!(!res.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_custom_prefixes() -> anyhow::Result<()> {
+    let input = test_patch_path("multi_hunk");
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let args = ["-o", dir, "--src-prefix", "old/", "--dst-prefix", "new/"];
+    assert!(spatch(&args, Some(&input))?.status.success());
+    let text = fs::read_to_string(out.path().join("src-lib.rs.patch"))?;
+    assert!(text.starts_with("diff --git old/src/lib.rs new/src/lib.rs\n"));
+    assert!(text.contains("\n--- old/src/lib.rs\n+++ new/src/lib.rs\n"));
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    assert!(
+        spatch(&["-o", dir, "--no-prefix"], Some(&input))?
+            .status
+            .success()
+    );
+    let text = fs::read_to_string(out.path().join("README.patch"))?;
+    assert!(text.starts_with("diff --git README README\n"));
+    assert!(text.contains("\n--- README\n+++ README\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_content_regex() -> anyhow::Result<()> {
+    let input = test_patch_path("multi_hunk");
+    let cases: [(&[&str], &[&str]); 4] = [
+        (&["--content-regex", "hello"], &["README.patch"]),
+        (
+            &["--content-regex", "world", "--content-side", "removed"],
+            &[],
+        ),
+        (
+            &["--content-regex", "^ +1$", "--content-side", "removed"],
+            &["src-lib.rs.patch"],
+        ),
+        // Context lines don't count.
+        (&["--content-regex", "three"], &[]),
+    ];
+
+    for (args, expected) in cases {
+        let out = tempfile::tempdir()?;
+        let dir = out.path().to_str().unwrap();
+        let res = spatch(&[&["-o", dir], args].concat(), Some(&input))?;
+        assert!(res.status.success());
+        assert_eq!(file_names(out.path())?, expected, "{:?}", args);
+    }
+
+    // The matching patch is still written whole.
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--content-regex", "[+] 0"], Some(&input))?;
+    assert!(res.status.success());
+    let text = fs::read_to_string(out.path().join("src-lib.rs.patch"))?;
+    assert_eq!(
+        text,
+        fs::read_to_string(&input)?
+            .split("diff --git a/README")
+            .next()
+            .unwrap()
+    );
+
+    Ok(())
+}
+
+/// Names and contents of the entries of a tar archive.
+fn tar_entries(archive: impl std::io::Read) -> anyhow::Result<Vec<(String, String)>> {
+    let mut entries = Vec::new();
+    for entry in tar::Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut text = String::new();
+        std::io::Read::read_to_string(&mut entry, &mut text)?;
+        entries.push((name, text));
+    }
+    Ok(entries)
+}
+
+#[test]
+fn test_tar_output() -> anyhow::Result<()> {
+    let input = test_patch_path("multi_hunk");
+    let out = tempfile::tempdir()?;
+    let archive = out.path().join("out.tar");
+    let archive_gz = out.path().join("out.tar.gz");
+
+    let res = spatch(&["--tar", archive.to_str().unwrap()], Some(&input))?;
+    assert!(res.status.success());
+    let res = spatch(
+        &["--tar-gz", archive_gz.to_str().unwrap(), "--split-by-hunk"],
+        Some(&input),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["out.tar", "out.tar.gz"]);
+
+    let entries = tar_entries(fs::File::open(&archive)?)?;
+    let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["src-lib.rs.patch", "README.patch"]);
+    assert!(entries[1].1.ends_with("-hello\n+hello world\n"));
+
+    let entries = tar_entries(flate2::read::GzDecoder::new(fs::File::open(&archive_gz)?))?;
+    let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["src-lib.rs#1.patch", "src-lib.rs#2.patch", "README#1.patch"]
+    );
+
+    // An archive asked for is written even if the patches are only checked
+    // otherwise.
+    let tree = tempfile::tempdir()?;
+    let worktree = tree.path().to_str().unwrap();
+    fs::write(tree.path().join("keep.txt"), "text\n")?;
+    for (args, input) in [
+        (
+            &["--verify", "--tar", archive.to_str().unwrap()][..],
+            test_patch_path("multi_hunk"),
+        ),
+        (
+            &[
+                "--worktree",
+                worktree,
+                "--tar-gz",
+                archive_gz.to_str().unwrap(),
+            ],
+            test_patch_path("binary_literal"),
+        ),
+    ] {
+        let res = spatch(args, Some(&input))?;
+        assert!(res.status.success(), "{:?}", args);
+    }
+    let entries = tar_entries(fs::File::open(&archive)?)?;
+    assert_eq!(entries.len(), 2);
+    let entries = tar_entries(flate2::read::GzDecoder::new(fs::File::open(&archive_gz)?))?;
+    let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["added.bin.patch", "doomed.bin.patch", "keep.txt.patch"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_chunk_size() -> anyhow::Result<()> {
+    let input = test_patch_path("naming");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "--chunk-size", "3"], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        ["chunk-0001.patch", "chunk-0002.patch"]
+    );
+    // Together, the chunks are the whole diff again.
+    let chunks = fs::read_to_string(out.path().join("chunk-0001.patch"))?
+        + &fs::read_to_string(out.path().join("chunk-0002.patch"))?;
+    assert_eq!(chunks, fs::read_to_string(&input)?);
+    let last = fs::read_to_string(out.path().join("chunk-0002.patch"))?;
+    assert!(last.starts_with("diff --git a/src/lib.rs b/src/lib.rs\n"));
+
+    let archive = out.path().join("out.tar");
+    let res = spatch(
+        &[
+            "--tar",
+            archive.to_str().unwrap(),
+            "--chunk-size",
+            "2",
+            "-n",
+        ],
+        Some(&test_patch_path("copy")),
+    )?;
+    assert!(res.status.success());
+    let entries = tar_entries(fs::File::open(&archive)?)?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].0, "chunk-0001.patch");
+    assert_eq!(entries[0].1.matches("diff --git ").count(), 2);
+
+    let res = spatch(&["-o", dir, "--chunk-size", "0"], Some(&input))?;
+    assert!(!res.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_reverse() -> anyhow::Result<()> {
+    let forward = test_patch_path("reversible");
+    let reversed = test_patch_path("reversible_reversed");
+    let out = tempfile::tempdir()?;
+    let chunk = out.path().join("chunk-0001.patch");
+
+    // Reversing the patch gives what git diff -R wrote, and reversing that
+    // gives the patch back.
+    for (input, expected) in [(&forward, &reversed), (&reversed, &forward)] {
+        let dir = out.path().to_str().unwrap();
+        let res = spatch(&["-o", dir, "-R", "--chunk-size", "100"], Some(input))?;
+        assert!(res.status.success());
+        assert_eq!(fs::read_to_string(&chunk)?, fs::read_to_string(expected)?);
+    }
+
+    // The names are still those of the files as diffed.
+    let split = tempfile::tempdir()?;
+    let dir = split.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--reverse"], Some(&forward))?;
+    assert!(res.status.success());
+    assert!(file_names(split.path())?.contains(&"new_name.txt.patch".to_string()));
+    let fresh = fs::read_to_string(split.path().join("fresh.txt.patch"))?;
+    assert!(fresh.contains("deleted file mode 100644\n"));
+
+    let res = spatch(&["-o", dir, "-R"], Some(&test_patch_path("copy")))?;
+    assert!(!res.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_dedup() -> anyhow::Result<()> {
+    // The first commit shows up on both branches, the last one was picked
+    // onto stable without the trailing whitespace it added on main.
+    let input = test_patch_path("duplicates");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?.len(), 7);
+
+    for (args, names) in [
+        (
+            &["--dedup"][..],
+            &[
+                "a.txt.patch",
+                "a.txt~2.patch",
+                "a.txt~3.patch",
+                "b.txt.patch",
+                "b.txt~2.patch",
+            ][..],
+        ),
+        (
+            &["--dedup", "--normalize-whitespace"],
+            &[
+                "a.txt.patch",
+                "a.txt~2.patch",
+                "b.txt.patch",
+                "b.txt~2.patch",
+            ],
+        ),
+    ] {
+        let out = tempfile::tempdir()?;
+        let dir = out.path().to_str().unwrap();
+        let res = spatch(&[&["-o", dir], args].concat(), Some(&input))?;
+        assert!(res.status.success());
+        assert_eq!(file_names(out.path())?, names);
+        let skipped = 7 - names.len();
+        let stderr = String::from_utf8(res.stderr)?;
+        assert!(stderr.contains(&format!("Skipped {} duplicate patches", skipped)));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_dedup_compares_lines() -> anyhow::Result<()> {
+    // Both bodies are " a+b+c" once their lines are joined, but the first
+    // keeps "a+b" and adds "c" where the second keeps "a" and adds "b+c".
+    let header = "diff --git a/x b/x\nindex 1111111..2222222 100644\n--- a/x\n+++ b/x\n";
+    let patch = format!(
+        "{h}@@ -1 +1,2 @@\n a+b\n+c\n{h}@@ -1 +1,2 @@\n a\n+b+c\n",
+        h = header
+    );
+    let input = tempfile::NamedTempFile::new()?;
+    fs::write(input.path(), patch)?;
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "--dedup"], Some(input.path()))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["x.patch", "x~2.patch"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_sort_numbered() -> anyhow::Result<()> {
+    // Patches of a.txt adding one line, then of the first commit adding two
+    // lines to a.txt and one to b.txt, twice over.
+    let input = test_patch_path("duplicates");
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--numbered"], Some(&input))?;
+    assert!(res.status.success());
+    let first = fs::read_to_string(out.path().join("0001-a.txt.patch"))?;
+    assert!(first.contains("+three"));
+    assert!(out.path().join("0003-b.txt.patch").exists());
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(
+        &["-o", dir, "--numbered", "--sort", "additions"],
+        Some(&input),
+    )?;
+    assert!(res.status.success());
+    let first = fs::read_to_string(out.path().join("0001-a.txt.patch"))?;
+    assert!(first.contains("new file mode 100644\n"));
+    assert_eq!(file_names(out.path())?.len(), 7);
+
+    // Ties keep their order, so the first commit's b.txt stays ahead.
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(
+        &[
+            "-o",
+            dir,
+            "--numbered",
+            "--sort",
+            "name",
+            "--max-patches",
+            "5",
+        ],
+        Some(&input),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        [
+            "0001-a.txt.patch",
+            "0002-a.txt.patch",
+            "0003-a.txt.patch",
+            "0004-a.txt.patch",
+            "0005-b.txt.patch"
+        ]
+    );
+    let b = fs::read_to_string(out.path().join("0005-b.txt.patch"))?;
+    assert!(b.contains("+bee\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_drop_whitespace_only() -> anyhow::Result<()> {
+    let input = test_patch_path("whitespace_only");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "--drop-whitespace-only", "-v"], Some(&input))?;
+    assert!(res.status.success());
+    assert!(
+        String::from_utf8(res.stderr)?
+            .contains("Dropped 1 whitespace-only patches and 1 whitespace-only hunks")
+    );
+    assert_eq!(file_names(out.path())?, ["lib.rs.patch", "plain.txt.patch"]);
+    let text = fs::read_to_string(out.path().join("lib.rs.patch"))?;
+    assert!(text.ends_with(
+        "+++ b/lib.rs\n@@ -9,5 +9,5 @@ fn a() {\n \n \n fn b() {\n-    two();\n+    three();\n }\n"
+    ));
+
+    let res = spatch(&["--list", "--drop-whitespace-only"], Some(&input))?;
+    assert_eq!(String::from_utf8(res.stdout)?, "M lib.rs\nM plain.txt\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_stat_output_input() -> anyhow::Result<()> {
+    let input = tempfile::NamedTempFile::new()?;
+    fs::write(
+        &input,
+        " lib.rs    | 4 ++--\n \
+         logo.png  | Bin 0 -> 120 bytes\n \
+         2 files changed, 2 insertions(+), 2 deletions(-)\n",
+    )?;
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    for args in [
+        &["-o", dir][..],
+        &["-o", dir, "--", input.path().to_str().unwrap()],
+    ] {
+        let res = spatch(args, Some(input.path()))?;
+        assert!(!res.status.success());
+        assert!(String::from_utf8(res.stderr)?.contains("looks like --stat output"));
+    }
+
+    // Other inputs without patches are still fine.
+    fs::write(&input, "nothing to see here\n")?;
+    let res = spatch(&["-o", dir], Some(input.path()))?;
+    assert!(res.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_checksums() -> anyhow::Result<()> {
+    use sha2::{Digest, Sha256};
+
+    let sha256 = |data: &[u8]| -> String {
+        Sha256::digest(data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    };
+    let input = test_patch_path("nested_dirs");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().join("patches");
+    fs::create_dir(&dir)?;
+    let sums = out.path().join("SHA256SUMS");
+
+    let res = spatch(
+        &[
+            "-o",
+            dir.to_str().unwrap(),
+            "--preserve-dirs",
+            "--checksums",
+            sums.to_str().unwrap(),
+        ],
+        Some(&input),
+    )?;
+    assert!(res.status.success());
+    let text = fs::read_to_string(&sums)?;
+    assert_eq!(text.lines().count(), 4);
+    for line in text.lines() {
+        let (sum, path) = line.split_once("  ").unwrap();
+        assert_eq!(sum, sha256(&fs::read(dir.join(path))?), "{}", path);
+    }
+
+    // Archive entries are hashed too, by their names in the archive.
+    let archive = out.path().join("out.tar");
+    let res = spatch(
+        &[
+            "--tar",
+            archive.to_str().unwrap(),
+            "--checksums",
+            sums.to_str().unwrap(),
+        ],
+        Some(&input),
+    )?;
+    assert!(res.status.success());
+    let text = fs::read_to_string(&sums)?;
+    let mut entries = tar_entries(fs::File::open(&archive)?)?;
+    entries.sort();
+    let expected: Vec<_> = entries
+        .iter()
+        .map(|(name, data)| format!("{}  {}", sha256(data.as_bytes()), name))
+        .collect();
+    assert_eq!(text.lines().collect::<Vec<_>>(), expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_skip_and_max_patches() -> anyhow::Result<()> {
+    let input = test_patch_path("patch_of_patches");
+    let cases: [(&[&str], &[&str]); 3] = [
+        (
+            &["--skip", "1"],
+            &[
+                "patches-add-feature-xyz.patch",
+                "patches-update-readme.patch",
+            ],
+        ),
+        (
+            &["--skip", "1", "--max-patches", "1"],
+            &["patches-add-feature-xyz.patch"],
+        ),
+        // Counted after filtering: the second new file.
+        (&["-n", "--skip", "1"], &["patches-add-feature-xyz.patch"]),
+    ];
+
+    for (args, expected) in cases {
+        let out = tempfile::tempdir()?;
+        let dir = out.path().to_str().unwrap();
+        let res = spatch(&[&["-o", dir], args].concat(), Some(&input))?;
+        assert!(res.status.success());
+        assert_eq!(file_names(out.path())?, expected, "{:?}", args);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_fail_on_empty() -> anyhow::Result<()> {
+    let input = test_patch_path("multi_hunk");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "--glob", "*.c"], Some(&input))?;
+    assert!(res.status.success());
+    let res = spatch(&["-o", dir, "--glob", "README", "-v"], Some(&input))?;
+    assert!(String::from_utf8(res.stderr)?.contains("Kept 1 of 2 patches"));
+
+    let res = spatch(
+        &["-o", dir, "--glob", "*.c", "--fail-on-empty"],
+        Some(&input),
+    )?;
+    assert!(!res.status.success());
+    assert!(String::from_utf8(res.stderr)?.contains("No patch passed the filters"));
+
+    let res = spatch(
+        &["-o", dir, "--glob", "README", "--fail-on-empty"],
+        Some(&input),
+    )?;
+    assert!(res.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_glob_basename() -> anyhow::Result<()> {
+    let input = test_patch_path("nested_dirs");
+    let both = vec!["src-main.rs.patch", "src-parser-lexer.rs.patch"];
+    let cases: [(&[&str], Vec<&str>); 6] = [
+        // The whole path is matched, and * doesn't stop at a /.
+        (&["--glob", "*.rs"], both.clone()),
+        (&["--glob", "src/*.rs"], both.clone()),
+        (&["--glob", "lexer.rs"], vec![]),
+        (&["--glob", "*.rs", "--glob-basename"], both.clone()),
+        (
+            &["--glob", "lexer.rs", "--glob-basename"],
+            vec!["src-parser-lexer.rs.patch"],
+        ),
+        (&["--glob", "src/*.rs", "--glob-basename"], vec![]),
+    ];
+    for (filter, expected) in cases {
+        let out = tempfile::tempdir()?;
+        let dir = out.path().join("out");
+        let mut args = vec!["-o", dir.to_str().unwrap()];
+        args.extend(filter);
+        let res = spatch(&args, Some(&input))?;
+        assert!(res.status.success(), "{:?}", filter);
+        let written = if dir.exists() {
+            file_names(&dir)?
+        } else {
+            vec![]
+        };
+        assert_eq!(written, expected, "{:?}", filter);
+    }
+
+    let res = spatch(&["--glob-basename", "-o", "out"], Some(&input))?;
+    assert!(!res.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_empty_input() -> anyhow::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let empty = tmp.path().join("empty.patch");
+    fs::write(&empty, "")?;
+    let out = tmp.path().join("out");
+    let dir = out.to_str().unwrap();
+
+    // Neither the file nor stdin makes the output directory or a sound.
+    for (args, stdin) in [
+        (&["-o", dir, "--files", empty.to_str().unwrap()][..], None),
+        (&["-o", dir], Some(&empty)),
+    ] {
+        let res = spatch(args, stdin.map(PathBuf::as_path))?;
+        assert!(res.status.success());
+        assert!(res.stdout.is_empty());
+        assert!(res.stderr.is_empty());
+        assert!(!out.exists());
+    }
+
+    let res = spatch(&["-o", dir, "--fail-on-empty"], Some(&empty))?;
+    assert!(!res.status.success());
+    assert!(!out.exists());
+
+    // Neither do inputs with text but no diff, which -v only counts.
+    let preamble = tmp.path().join("preamble.patch");
+    fs::write(
+        &preamble,
+        "From: Jane Doe <jane@example.com>\nSubject: notes\n\nNo diff.\n",
+    )?;
+    for input in [preamble, test_patch_path("nongit")] {
+        let input = input.to_str().unwrap();
+        let res = spatch(&["-o", dir, "--files", input], None)?;
+        assert!(res.status.success());
+        assert!(res.stderr.is_empty());
+        let res = spatch(&["-o", dir, "-v", "--files", input], None)?;
+        assert!(res.status.success());
+        let stderr = String::from_utf8(res.stderr)?;
+        assert!(!stderr.contains("Split "), "{}", stderr);
+        assert!(!out.exists());
+    }
+
+    // The directory is made once there is something to write to it, parents
+    // included.
+    let nested = out.join("new").join("subdir");
+    let res = spatch(
+        &["-o", nested.to_str().unwrap()],
+        Some(&test_patch_path("multi")),
+    )?;
+    assert!(res.status.success());
+    assert!(!file_names(&nested)?.is_empty());
+    let stderr = String::from_utf8(res.stderr)?;
+    assert!(stderr.contains(&format!(
+        "Made the missing output directory {}",
+        nested.display()
+    )));
+
+    // Writing into it again, it's no longer news.
+    let res = spatch(
+        &["-o", nested.to_str().unwrap()],
+        Some(&test_patch_path("multi")),
+    )?;
+    assert!(res.status.success());
+    assert!(res.stderr.is_empty());
+
+    let res = spatch(&["-o", empty.to_str().unwrap()], Some(&empty))?;
+    assert!(!res.status.success());
+    let stderr = String::from_utf8(res.stderr)?;
+    assert!(stderr.contains(&format!(
+        "Output path {} is not a directory",
+        empty.display()
+    )));
+
+    Ok(())
+}
+
+#[test]
+fn test_git_log_input() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir], Some(&test_patch_path("git_log")))?;
+    assert!(res.status.success());
+    // Every change is kept, even when a file changes in several commits.
+    assert_eq!(
+        file_names(out.path())?,
+        [
+            "one.txt.patch",
+            "one.txt~2.patch",
+            "two.txt.patch",
+            "two.txt~2.patch"
+        ]
+    );
+    let text = fs::read_to_string(out.path().join("two.txt~2.patch"))?;
+    assert!(text.starts_with("diff --git a/two.txt b/two.txt\nnew file mode 100644\n"));
+    assert!(text.ends_with("@@ -0,0 +1 @@\n+x\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_only_new_keeps_copies() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "-n"], Some(&test_patch_path("copy")))?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        ["edited.txt.patch", "exact.txt.patch"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_only_removed() -> anyhow::Result<()> {
+    let input = test_patch_path("naming");
+
+    // -r used to check for added files a second time, and so filtered nothing.
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "-r"], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["docs-old.md.patch"]);
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "-r", "-x"], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["docs-old.md"]);
+    assert_eq!(fs::read_to_string(out.path().join("docs-old.md"))?, "old\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_normalize_whitespace() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(
+        &["-o", dir, "--normalize-whitespace"],
+        Some(&test_patch_path("trailing_whitespace")),
+    )?;
+    assert!(res.status.success());
+    assert!(
+        String::from_utf8(res.stderr)?.contains("trimmed trailing whitespace off 1 added lines")
+    );
+    // Context and removed lines keep theirs, or the patch wouldn't apply.
+    assert_eq!(
+        fs::read_to_string(out.path().join("ws.txt.patch"))?,
+        "diff --git a/ws.txt b/ws.txt\n\
+         index e516e27..7eb495f 100644\n\
+         --- a/ws.txt\n\
+         +++ b/ws.txt\n\
+         @@ -1,3 +1,4 @@\n \
+         keep \n\
+         -old\t\n\
+         +new\n \
+         end\n\
+         +added\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_on_collision() -> anyhow::Result<()> {
+    // a-b/c and a/b-c both flatten to a-b-c.
+    let input = test_patch_path("flatten_collision");
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["a-b-c.patch", "a-b-c~2.patch"]);
+    assert!(fs::read_to_string(out.path().join("a-b-c~2.patch"))?.contains("+ONE"));
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--on-collision", "overwrite"], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["a-b-c.patch"]);
+    assert!(fs::read_to_string(out.path().join("a-b-c.patch"))?.contains("+ONE"));
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--on-collision", "error"], Some(&input))?;
+    assert!(!res.status.success());
+    assert!(
+        String::from_utf8(res.stderr)?
+            .contains("the patches of a-b/c and a/b-c would both be written to 'a-b-c'")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_on_collision_across_inputs() -> anyhow::Result<()> {
+    // Both inputs are named x, so their patches get the same names.
+    let inputs = tempfile::tempdir()?;
+    let naming = fs::read_to_string(test_patch_path("naming"))?;
+    for (dir, text) in [
+        ("a", naming.clone()),
+        ("b", naming.replace("+more", "+other")),
+    ] {
+        fs::create_dir(inputs.path().join(dir))?;
+        fs::write(inputs.path().join(dir).join("x.patch"), text)?;
+    }
+    let first = inputs.path().join("a/x.patch");
+    let second = inputs.path().join("b/x.patch");
+    let multi_hunk = test_patch_path("multi_hunk");
+    let files = [
+        first.to_str().unwrap(),
+        second.to_str().unwrap(),
+        multi_hunk.to_str().unwrap(),
+    ];
+
+    let split = |args: &[&str]| -> anyhow::Result<(Output, tempfile::TempDir)> {
+        let out = tempfile::tempdir()?;
+        let mut all = vec!["-o", out.path().to_str().unwrap()];
+        all.extend(args);
+        all.extend(["--files"]);
+        all.extend(files);
+        Ok((spatch(&all, None)?, out))
+    };
+
+    for jobs in ["1", "3"] {
+        let (res, out) = split(&["-j", jobs])?;
+        assert!(res.status.success());
+        let names = file_names(out.path())?;
+        assert_eq!(names.len(), 10);
+        assert!(names.contains(&"src-lib.rs+x~2.patch".to_string()));
+        let second = fs::read_to_string(out.path().join("src-lib.rs+x~2.patch"))?;
+        assert!(second.contains("+other"));
+
+        let (res, _out) = split(&["-j", jobs, "--on-collision", "error"])?;
+        assert!(!res.status.success());
+        assert!(String::from_utf8(res.stderr)?.contains(
+            "the patches of docs/new.md and docs/new.md would both be written to 'docs-new.md+x'"
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_context() -> anyhow::Result<()> {
+    let input = test_patch_path("context");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "--context", "2"], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(
+        fs::read_to_string(out.path().join("n.txt.patch"))?,
+        "diff --git a/n.txt b/n.txt\n\
+         index e8823e1..ba8d80c 100644\n\
+         --- a/n.txt\n\
+         +++ b/n.txt\n\
+         @@ -3,9 +3,9 @@\n \
+         3\n \
+         4\n\
+         -5\n\
+         +five\n \
+         6\n \
+         7\n \
+         8\n\
+         -9\n\
+         +nine\n \
+         10\n \
+         11\n\
+         @@ -23,5 +23,5 @@\n \
+         23\n \
+         24\n\
+         -25\n\
+         +twenty-five\n \
+         26\n \
+         27\n"
+    );
+
+    // The first hunk's changes are 3 lines apart, so it stays whole.
+    let res = spatch(&["-o", dir, "--context", "1"], Some(&input))?;
+    assert!(res.status.success());
+    assert!(String::from_utf8(res.stderr)?.contains("left hunk '@@ -2,11 +2,11 @@' as it was"));
+    let patch = fs::read_to_string(out.path().join("n.txt.patch"))?;
+    assert!(patch.contains("@@ -2,11 +2,11 @@\n"));
+    assert!(patch.contains("@@ -24,3 +24,3 @@\n 24\n-25\n+twenty-five\n 26\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_mode_only_change() -> anyhow::Result<()> {
+    let input = test_patch_path("mode_only");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "--split-by-hunk"], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["a.txt#1.patch", "run.sh.patch"]);
+    assert_eq!(
+        fs::read_to_string(out.path().join("run.sh.patch"))?,
+        "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n"
+    );
+
+    // Neither added nor removed.
+    for flag in ["-n", "-r"] {
+        let out = tempfile::tempdir()?;
+        let dir = out.path().to_str().unwrap();
+        let res = spatch(&["-o", dir, flag], Some(&input))?;
+        assert!(res.status.success());
+        assert!(file_names(out.path())?.is_empty());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_quiet_and_verbose() -> anyhow::Result<()> {
+    let input = test_patch_path("multi");
+    let input = input.to_str().unwrap();
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "--files", input], None)?;
+    assert!(res.status.success());
+    assert!(res.stdout.is_empty());
+    let stderr = String::from_utf8(res.stderr)?;
+    assert!(stderr.contains("Split "));
+    assert!(!stderr.contains("Wrote "));
+
+    let res = spatch(&["-o", dir, "-q", "--files", input], None)?;
+    assert!(res.status.success());
+    assert!(res.stderr.is_empty());
+
+    let res = spatch(&["-o", dir, "-v", "--files", input], None)?;
+    assert!(res.status.success());
+    let stderr = String::from_utf8(res.stderr)?;
+    assert!(stderr.contains("Wrote one+multi.patch\n"));
+    assert!(stderr.contains("Wrote two+multi.patch\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_combine() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir], Some(&test_patch_path("multi")))?;
+    assert!(res.status.success());
+
+    // The blank lines and trailing text of the input aren't part of any patch.
+    let res = spatch(&["combine", dir], None)?;
+    assert!(res.status.success());
+    assert_eq!(
+        String::from_utf8(res.stdout)?,
+        "diff --git a/one b/one\n\
+         --- a/one\n\
+         +++ b/one\n\
+         @@ -0,0 +1,1 @@\n\
+         +one\n\
+         diff --git a/two b/two\n\
+         --- a/two\n\
+         +++ b/two\n\
+         @@ -0,0 +1,1 @@\n\
+         +two\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_files_after_double_dash() -> anyhow::Result<()> {
+    let inputs = tempfile::tempdir()?;
+    let dashed = inputs.path().join("-b.patch");
+    fs::copy(test_patch_path("multi"), inputs.path().join("a.patch"))?;
+    fs::copy(test_patch_path("multi"), &dashed)?;
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_spatch"));
+    let res = cmd
+        .current_dir(inputs.path())
+        .args(["-o", dir, "--files", "--", "a.patch", "-b.patch"])
+        .output()?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        ["one+-b.patch", "one+a.patch", "two+-b.patch", "two+a.patch"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_ignore_case() -> anyhow::Result<()> {
+    let input = test_patch_path("multi_hunk");
+
+    for filter in [["--glob", "readme"], ["--regex", "^readme$"]] {
+        let out = tempfile::tempdir()?;
+        let dir = out.path().to_str().unwrap();
+
+        let res = spatch(&[&["-o", dir][..], &filter].concat(), Some(&input))?;
+        assert!(res.status.success());
+        assert!(file_names(out.path())?.is_empty());
+
+        let res = spatch(
+            &[&["-o", dir, "--ignore-case"][..], &filter].concat(),
+            Some(&input),
+        )?;
+        assert!(res.status.success());
+        assert_eq!(file_names(out.path())?, ["README.patch"]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_malformed_hunk_warning() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "-q"], Some(&test_patch_path("malformed")))?;
+    assert!(res.status.success());
+    assert!(
+        String::from_utf8(res.stderr)?
+            .contains("warning: f: couldn't parse hunk header '@@ -1,2 1,2 @@'")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_input_dir() -> anyhow::Result<()> {
+    let input = tempfile::tempdir()?;
+    fs::create_dir_all(input.path().join("sub/deeper"))?;
+    fs::copy(test_patch_path("multi"), input.path().join("multi.patch"))?;
+    fs::copy(
+        test_patch_path("naming"),
+        input.path().join("sub/deeper/naming.patch"),
+    )?;
+    fs::write(input.path().join("sub/notes.txt"), "not a patch\n")?;
+    let input_dir = input.path().to_str().unwrap();
+
+    // Found at any depth, and only by extension.
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--input-dir", input_dir], None)?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        [
+            "docs-new.md+naming.patch",
+            "docs-old.md+naming.patch",
+            "one+multi.patch",
+            "src-b.rs+naming.patch",
+            "src-lib.rs+naming.patch",
+            "two+multi.patch"
+        ]
+    );
+
+    // Split along with the files named on their own.
+    let copy = test_patch_path("copy");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(
+        &[
+            "-o",
+            dir,
+            "--files",
+            copy.to_str().unwrap(),
+            "--input-dir",
+            &input.path().join("sub").to_string_lossy(),
+        ],
+        None,
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        [
+            "docs-new.md+naming.patch",
+            "docs-old.md+naming.patch",
+            "edited.txt+copy.patch",
+            "exact.txt+copy.patch",
+            "src-b.rs+naming.patch",
+            "src-lib.rs+naming.patch",
+            "src.txt+copy.patch"
+        ]
+    );
+
+    // A directory without patches is no reason to read stdin instead.
+    let empty = tempfile::tempdir()?;
+    let empty_dir = empty.path().to_str().unwrap();
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(
+        &["-o", dir, "--input-dir", empty_dir],
+        Some(&test_patch_path("multi")),
+    )?;
+    assert!(res.status.success());
+    assert!(file_names(out.path())?.is_empty());
+
+    let res = spatch(
+        &[
+            "-o",
+            dir,
+            "--input-dir",
+            &input.path().join("missing").to_string_lossy(),
+        ],
+        None,
+    )?;
+    assert!(!res.status.success());
+    assert!(String::from_utf8(res.stderr)?.contains("is not a directory"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_no_input_on_a_terminal() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch_on_tty(&["-o", dir])?;
+    assert!(!res.status.success());
+    assert!(String::from_utf8(res.stderr)?.contains("No input given"));
+
+    let empty = tempfile::tempdir()?;
+    let empty_dir = empty.path().to_str().unwrap();
+    for args in [&["-o", dir][..], &["--list"]] {
+        let res = spatch_on_tty(&[args, &["--input-dir", empty_dir]].concat())?;
+        assert!(!res.status.success(), "{:?}", args);
+        assert!(
+            String::from_utf8(res.stderr)?
+                .contains("No patch files found by --input-dir or --files-from")
+        );
+    }
+
+    // Files to split leave the terminal alone.
+    let multi = test_patch_path("multi");
+    let res = spatch_on_tty(&["-o", dir, "--files", multi.to_str().unwrap()])?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        ["one+multi.patch", "two+multi.patch"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_diff_extension() -> anyhow::Result<()> {
+    let input = tempfile::tempdir()?;
+    fs::copy(test_patch_path("multi"), input.path().join("multi.diff"))?;
+    fs::copy(test_patch_path("naming"), input.path().join("naming.patch"))?;
+    fs::copy(test_patch_path("copy"), input.path().join("copy.txt"))?;
+    let input_dir = input.path().to_str().unwrap();
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(
+        &["-o", dir, "--input-dir", input_dir, "--extension", "diff"],
+        None,
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        [
+            "docs-new.md+naming.diff",
+            "docs-old.md+naming.diff",
+            "one+multi.diff",
+            "src-b.rs+naming.diff",
+            "src-lib.rs+naming.diff",
+            "two+multi.diff"
+        ]
+    );
+
+    // Split .diff files join back together just the same.
+    let res = spatch(&["combine", dir], None)?;
+    assert!(res.status.success());
+    assert_eq!(
+        String::from_utf8(res.stdout)?
+            .matches("diff --git ")
+            .count(),
+        6
+    );
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_follow_symlinks() -> anyhow::Result<()> {
+    use std::os::unix::fs::symlink;
+
+    let input = tempfile::tempdir()?;
+    fs::copy(test_patch_path("multi"), input.path().join("multi.patch"))?;
+    symlink("multi.patch", input.path().join("linked.patch"))?;
+    // Would loop forever if followed blindly.
+    symlink(".", input.path().join("self"))?;
+    let input_dir = input.path().to_str().unwrap();
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--input-dir", input_dir], None)?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        ["one+multi.patch", "two+multi.patch"]
+    );
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(
+        &["-o", dir, "--input-dir", input_dir, "--follow-symlinks"],
+        None,
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        [
+            "one+linked.patch",
+            "one+multi.patch",
+            "two+linked.patch",
+            "two+multi.patch"
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_files_from() -> anyhow::Result<()> {
+    let lists = tempfile::tempdir()?;
+    let multi = test_patch_path("multi");
+    let missing = lists.path().join("missing.patch");
+    let list = lists.path().join("list.txt");
+    fs::write(&list, format!("{}\n\n", multi.display()))?;
+    let list0 = lists.path().join("list0.txt");
+    fs::write(
+        &list0,
+        format!("{}\0{}\0", multi.display(), missing.display()),
+    )?;
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--files-from", list.to_str().unwrap()], None)?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        ["one+multi.patch", "two+multi.patch"]
+    );
+
+    // The list itself can come through stdin.
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--files-from", "-"], Some(&list))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?.len(), 2);
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--files-from0", list0.to_str().unwrap()], None)?;
+    assert!(!res.status.success());
+    let stderr = String::from_utf8(res.stderr)?;
+    assert!(stderr.contains("entry 2 names"));
+    assert!(file_names(out.path())?.is_empty());
+
+    let res = spatch(
+        &[
+            "-o",
+            dir,
+            "--files-from0",
+            list0.to_str().unwrap(),
+            "--skip-missing",
+        ],
+        None,
+    )?;
+    assert!(res.status.success());
+    assert!(String::from_utf8(res.stderr)?.contains("missing.patch, it doesn't exist"));
+    assert_eq!(file_names(out.path())?.len(), 2);
+
+    // An empty list is nothing to split, rather than a cue to read stdin.
+    let empty = lists.path().join("empty.txt");
+    fs::write(&empty, "")?;
+    let res = spatch(&["--files-from", empty.to_str().unwrap()], None)?;
+    assert!(res.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_output_names() -> anyhow::Result<()> {
+    let input = test_patch_path("naming");
+
+    for (args, expected) in [
+        (
+            &[][..],
+            &[
+                "docs-new.md.patch",
+                "docs-old.md.patch",
+                "src-b.rs.patch",
+                "src-lib.rs.patch",
+            ][..],
+        ),
+        (&["-n"], &["docs-new.md.patch"]),
+        (&["-r"], &["docs-old.md.patch"]),
+    ] {
+        let out = tempfile::tempdir()?;
+        let dir = out.path().to_str().unwrap();
+        let res = spatch(&[&["-o", dir][..], args].concat(), Some(&input))?;
+        assert!(res.status.success());
+        assert_eq!(file_names(out.path())?, expected, "{:?}", args);
+    }
+
+    // Nothing to name a patch after isn't worth a panic.
+    let input = tempfile::NamedTempFile::new()?;
+    fs::write(&input, "diff --git a/x b/x\n--- /dev/null\n+++ /dev/null\n")?;
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir], Some(input.path()))?;
+    assert!(res.status.success());
+    assert!(String::from_utf8(res.stderr)?.contains("/dev/null on both sides"));
+    assert!(file_names(out.path())?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_empty_file_round_trip() -> anyhow::Result<()> {
+    let input = test_patch_path("empty_file");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        ["d-also_empty.patch", "empty.patch"]
+    );
+    // Without a body, the header is all there is and has to come out whole.
+    let original = fs::read_to_string(&input)?;
+    let split = fs::read_to_string(out.path().join("d-also_empty.patch"))?
+        + &fs::read_to_string(out.path().join("empty.patch"))?;
+    assert_eq!(split, original);
+    assert!(split.contains("--- /dev/null\n+++ b/empty\n"));
+
+    let repo = tempfile::tempdir()?;
+    let git = |args: &[&str]| -> anyhow::Result<bool> {
+        Ok(Command::new("git")
+            .args(args)
+            .current_dir(repo.path())
+            .status()?
+            .success())
+    };
+    assert!(git(&["init", "-q"])?);
+    for name in file_names(out.path())? {
+        assert!(git(&["apply", out.path().join(name).to_str().unwrap()])?);
+    }
+    assert_eq!(fs::read(repo.path().join("empty"))?, b"");
+    assert_eq!(fs::read(repo.path().join("d").join("also_empty"))?, b"");
+
+    Ok(())
+}
+
+#[test]
+fn test_encoding() -> anyhow::Result<()> {
+    let input = test_patch_path("latin1");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    // By default, the split stops at the first line that isn't UTF-8, and
+    // leaves out the patch it cuts short. Only --strict fails on it.
+    // Moving other.txt first gives the Latin-1 line a patch to follow.
+    let inputs = tempfile::tempdir()?;
+    let reordered = inputs.path().join("reordered.patch");
+    let bytes = fs::read(&input)?;
+    let second = bytes
+        .windows(12)
+        .position(|w| w == b"\ndiff --git ")
+        .unwrap()
+        + 1;
+    fs::write(&reordered, [&bytes[second..], &bytes[..second]].concat())?;
+    for encoding in [&[][..], &["--encoding", "utf8-strict"]] {
+        let out = tempfile::tempdir()?;
+        let args = [&["-o", out.path().to_str().unwrap()], encoding].concat();
+        let res = spatch(&args, Some(&reordered))?;
+        assert!(res.status.success());
+        let stderr = String::from_utf8(res.stderr)?;
+        assert!(stderr.contains("latin1.txt: left out the patch"));
+        assert!(stderr.contains("stopped at line 13, which is not valid UTF-8"));
+        assert_eq!(file_names(out.path())?, ["other.txt.patch"]);
+    }
+
+    let strict = tempfile::tempdir()?;
+    let res = spatch(
+        &["-o", strict.path().to_str().unwrap(), "--strict"],
+        Some(&reordered),
+    )?;
+    assert!(!res.status.success());
+    assert!(String::from_utf8(res.stderr)?.contains("line 13 is not valid UTF-8"));
+    assert_eq!(file_names(strict.path())?, ["other.txt.patch"]);
+
+    let res = spatch(&["-o", dir, "--encoding", "utf8-lossy"], Some(&input))?;
+    assert!(res.status.success());
+    assert!(fs::read_to_string(out.path().join("latin1.txt.patch"))?.contains(" caf\u{FFFD}\n"));
+
+    // The raw bytes make it through unchanged.
+    let res = spatch(&["-o", dir, "--encoding", "bytes"], Some(&input))?;
+    assert!(res.status.success());
+    let mut split = fs::read(out.path().join("latin1.txt.patch"))?;
+    split.extend(fs::read(out.path().join("other.txt.patch"))?);
+    assert_eq!(split, fs::read(&input)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_subcommands() -> anyhow::Result<()> {
+    let input = test_patch_path("naming");
+
+    // `split` is what runs without a subcommand, too.
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["split", "-o", dir, "-r"], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["docs-old.md.patch"]);
+
+    let res = spatch(&["stats", input.to_str().unwrap()], None)?;
+    assert!(res.status.success());
+    assert_eq!(
+        String::from_utf8(res.stdout)?,
+        " docs/new.md          | 1 +\n\
+        \x20docs/old.md          | 1 -\n\
+        \x20src/a.rs => src/b.rs | 0\n\
+        \x20src/lib.rs           | 1 +\n\
+        \x204 files changed, 2 insertions(+), 1 deletion(-)\n"
+    );
+
+    let res = spatch(&["verify", "-q"], Some(&input))?;
+    assert!(res.status.success());
+    assert!(res.stderr.is_empty());
+    let res = spatch(&["verify"], Some(&test_patch_path("mismatch")))?;
+    assert!(!res.status.success());
+    assert!(String::from_utf8(res.stderr)?.contains("declares -1 +1 lines but has -0 +1"));
+
+    // Split flags don't go with other subcommands.
+    let res = spatch(&["-o", dir, "stats"], Some(&input))?;
+    assert!(!res.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_color() -> anyhow::Result<()> {
+    let input = test_patch_path("naming");
+    let path = input.to_str().unwrap();
+
+    let res = spatch(&["stats", path, "--color", "always"], None)?;
+    assert!(res.status.success());
+    let text = String::from_utf8(res.stdout)?;
+    assert!(text.contains(" docs/old.md          | 1 \x1b[31m-\x1b[m\n"));
+    assert!(
+        text.ends_with("changed, \x1b[32m2 insertions(+)\x1b[m, \x1b[31m1 deletion(-)\x1b[m\n")
+    );
+
+    let res = spatch(&["--list", "--color=always"], Some(&input))?;
+    assert!(res.status.success());
+    let text = String::from_utf8(res.stdout)?;
+    assert!(text.starts_with("\x1b[32mA\x1b[m docs/new.md\n"));
+    assert!(text.contains("\x1b[36mR\x1b[m src/a.rs -> src/b.rs\n"));
+
+    // Pipes aren't terminals, so auto leaves the output alone.
+    for args in [&["--list"][..], &["--list", "--color", "never"]] {
+        let res = spatch(args, Some(&input))?;
+        assert!(res.status.success());
+        assert!(!res.stdout.contains(&0x1b));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_include_commit_headers() -> anyhow::Result<()> {
+    let input = test_patch_path("git_log");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "--include-commit-headers"], Some(&input))?;
+    assert!(res.status.success());
+    let text = fs::read_to_string(out.path().join("one.txt.patch"))?;
+    assert!(text.starts_with(
+        "commit 5d8061e1af4adcc13b3657fe491cc307b9e93ce1\n\
+         Author: Jane Doe <jane@example.com>\n\
+         \n    Grow both\n    \n        indented body line\n\
+         \ndiff --git a/one.txt b/one.txt\n"
+    ));
+
+    // Splitting the output again finds the same commit.
+    let again = tempfile::tempdir()?;
+    let res = spatch(
+        &[
+            "-o",
+            again.path().to_str().unwrap(),
+            "--include-commit-headers",
+            "--",
+            out.path().join("one.txt.patch").to_str().unwrap(),
+        ],
+        None,
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        fs::read_to_string(again.path().join("one.txt+one.txt.patch"))?,
+        text
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_hg_diff() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir], Some(&test_patch_path("hg")))?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        [
+            "docs-new file.md.patch",
+            "old.txt.patch",
+            "src-main.rs.patch"
+        ]
+    );
+    let text = fs::read_to_string(out.path().join("old.txt.patch"))?;
+    assert!(text.starts_with("diff -r 1a2b3c4d5e6f old.txt\n--- a/old.txt\t"));
+    assert!(text.ends_with("@@ -1,1 +0,0 @@\n-bye\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_submodule() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir], Some(&test_patch_path("submodule")))?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        [
+            ".gitmodules.patch",
+            "README.patch",
+            "libs-sub.patch",
+            "vendor-tool.patch"
+        ]
+    );
+    let text = fs::read_to_string(out.path().join("libs-sub.patch"))?;
+    assert!(text.starts_with("diff --git a/libs/sub b/libs/sub\nindex cb42920..dbacf54 160000\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_since_until() -> anyhow::Result<()> {
+    let input = test_patch_path("dated_log");
+
+    for (args, expected) in [
+        (&["--since", "2024-02-01"][..], "A feb.txt\nA mar.txt\n"),
+        // The February commit was made on March 1st in UTC.
+        (&["--until", "2024-02-29"], "A jan.txt\n"),
+        (
+            &[
+                "--since",
+                "2024-01-15",
+                "--until",
+                "2024-03-01T00:00:00-05:00",
+            ],
+            "A jan.txt\nA feb.txt\n",
+        ),
+        (&["--since", "2025-01-01"], ""),
+    ] {
+        let res = spatch(&[&["--list"], args].concat(), Some(&input))?;
+        assert!(res.status.success());
+        assert_eq!(String::from_utf8(res.stdout)?, expected, "{:?}", args);
+    }
+
+    // Diffs without commits have no date to be in range.
+    let res = spatch(
+        &["--list", "--since", "2000-01-01"],
+        Some(&test_patch_path("multi")),
+    )?;
+    assert_eq!(String::from_utf8(res.stdout)?, "");
+
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--since", "2024-03-02"], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(out.path())?, ["mar.txt.patch"]);
+
+    let res = spatch(&["--list", "--since", "last tuesday"], Some(&input))?;
+    assert!(!res.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_list() -> anyhow::Result<()> {
+    let res = spatch(&["--list"], Some(&test_patch_path("naming")))?;
+    assert!(res.status.success());
+    assert_eq!(
+        String::from_utf8(res.stdout)?,
+        "A docs/new.md\nD docs/old.md\nR src/a.rs -> src/b.rs\nM src/lib.rs\n"
+    );
+
+    let res = spatch(&["--list", "-n"], Some(&test_patch_path("copy")))?;
+    assert!(res.status.success());
+    assert_eq!(
+        String::from_utf8(res.stdout)?,
+        "C src.txt -> edited.txt\nC src.txt -> exact.txt\n"
+    );
+
+    let res = spatch(
+        &["--list", "--content-regex", "more"],
+        Some(&test_patch_path("naming")),
+    )?;
+    assert_eq!(String::from_utf8(res.stdout)?, "M src/lib.rs\n");
+
+    // Listed in the order and with the renames the split would write.
+    let input = test_patch_path("unrenamed");
+    let res = spatch(
+        &["--list", "--merge-renames", "--sort", "name"],
+        Some(&input),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        String::from_utf8(res.stdout)?,
+        "R z_old.txt -> a_new.txt\n\
+         R empty_old -> empty_new\n\
+         A fresh.txt\n\
+         D gone.txt\n\
+         R docs.md -> guide/docs.md\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_renames() -> anyhow::Result<()> {
+    let input = test_patch_path("unrenamed");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    // The same as git diff -M would have written.
+    let res = spatch(
+        &["-o", dir, "--merge-renames", "--chunk-size", "100"],
+        Some(&input),
+    )?;
+    assert!(res.status.success());
+    assert_eq!(
+        fs::read_to_string(out.path().join("chunk-0001.patch"))?,
+        fs::read_to_string(test_patch_path("unrenamed_merged"))?
+    );
+
+    let split = tempfile::tempdir()?;
+    let dir = split.path().to_str().unwrap();
+    let res = spatch(&["-o", dir, "--merge-renames"], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(split.path())?,
+        [
+            "a_new.txt.patch",
+            "empty_new.patch",
+            "fresh.txt.patch",
+            "gone.txt.patch",
+            "guide-docs.md.patch"
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_mailbox() -> anyhow::Result<()> {
+    let input = test_patch_path("mailbox_log");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "--mailbox"], Some(&input))?;
+    assert!(res.status.success());
+    let names = file_names(out.path())?;
+    assert_eq!(
+        names,
+        [
+            "0001-main.rs.patch",
+            "0002-notes.md.patch",
+            "0003-main.rs.patch",
+            "0004-notes.md.patch"
+        ]
+    );
+    let mail = fs::read_to_string(out.path().join("0003-main.rs.patch"))?;
+    assert!(mail.starts_with(
+        "From cba12814b73c382c9b038a809463f5a98e9a64fc Mon Sep 17 00:00:00 2001\n\
+         From: Jane Doe <jane@example.com>\n\
+         Date: Sat, 2 Mar 2024 11:30:00 +0100\n\
+         Subject: [PATCH] Greet the user\n\
+         \n\
+         Both the program and its notes\n\
+         say hi now.\n\
+         ---\n\
+         diff --git a/main.rs b/main.rs\n"
+    ));
+    let signature = format!("-- \nspatch {}\n\n", env!("CARGO_PKG_VERSION"));
+    assert!(mail.ends_with(&format!("+}}\n{}", signature)));
+
+    // git am makes every patch a commit of its own, with the author, date
+    // and message of the one it came from.
+    let repo = tempfile::tempdir()?;
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args([
+                "-c",
+                "user.name=Committer",
+                "-c",
+                "user.email=c@example.com",
+            ])
+            .args(args)
+            .current_dir(repo.path())
+            .output()
+    };
+    assert!(git(&["init", "-q"])?.status.success());
+    let mails: Vec<_> = names.iter().map(|n| out.path().join(n)).collect();
+    let mails: Vec<_> = mails.iter().map(|m| m.to_str().unwrap()).collect();
+    assert!(git(&[&["am", "-q"][..], &mails].concat())?.status.success());
+    let log = git(&["log", "--format=%an %ad %s", "--date=short"])?;
+    assert_eq!(
+        String::from_utf8(log.stdout)?,
+        "Jane Doe 2024-03-02 Greet the user\n".repeat(2)
+            + &"Jane Doe 2024-03-01 Start the project\n".repeat(2)
+    );
+    assert_eq!(
+        fs::read_to_string(repo.path().join("notes.md"))?,
+        "# Notes\n\nSay hi.\n"
+    );
+
+    // Without the commit, the subject names the file and git's identity
+    // authors it.
+    let plain = tempfile::tempdir()?;
+    let res = Command::new(env!("CARGO_BIN_EXE_spatch"))
+        .args(["-o", plain.path().to_str().unwrap(), "--mailbox"])
+        .env("GIT_AUTHOR_NAME", "Sam Smith")
+        .env("GIT_AUTHOR_EMAIL", "sam@example.com")
+        .stdin(fs::File::open(test_patch_path("reversible"))?)
+        .output()?;
+    assert!(res.status.success());
+    let mail = fs::read_to_string(plain.path().join("0004-new_name.txt.patch"))?;
+    assert!(mail.starts_with(
+        "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001\n\
+         From: Sam Smith <sam@example.com>\n\
+         Subject: [PATCH] Rename old_name.txt to new_name.txt\n\
+         \n\
+         ---\n"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_template_file() -> anyhow::Result<()> {
+    let input = test_patch_path("reversible");
+    let tmp = tempfile::tempdir()?;
+    let template = tmp.path().join("template");
+    let template_path = template.to_str().unwrap();
+    let out = tmp.path().join("out");
+    let dir = out.to_str().unwrap();
+
+    fs::write(
+        &template,
+        "# {filename}: +{additions} -{deletions} {{kept}}\n{original_header}\n",
+    )?;
+    let res = spatch(&["-o", dir, "--template-file", template_path], Some(&input))?;
+    assert!(res.status.success());
+    let patch = fs::read_to_string(out.join("edit.txt.patch"))?;
+    assert!(patch.starts_with(
+        "# edit.txt: +2 -1 {kept}\n\
+         diff --git a/edit.txt b/edit.txt\n\
+         index c9e9e05..5c377c6 100644\n\
+         --- a/edit.txt\n\
+         +++ b/edit.txt\n\
+         @@ -1,10 +1,11 @@\n"
+    ));
+    // The counts follow the patch around.
+    let res = spatch(
+        &["-o", dir, "-R", "--template-file", template_path],
+        Some(&input),
+    )?;
+    assert!(res.status.success());
+    let patch = fs::read_to_string(out.join("edit.txt.patch"))?;
+    assert!(patch.starts_with("# edit.txt: +1 -2 {kept}\n"));
+
+    // Unknown placeholders are refused before anything is written.
+    fs::write(&template, "{original_header}{author}")?;
+    let empty = tmp.path().join("empty");
+    let res = spatch(
+        &[
+            "-o",
+            empty.to_str().unwrap(),
+            "--template-file",
+            template_path,
+        ],
+        Some(&input),
+    )?;
+    assert!(!res.status.success());
+    assert!(String::from_utf8(res.stderr)?.contains("unknown placeholder {author}"));
+    assert!(!empty.exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_strict() -> anyhow::Result<()> {
+    let input = test_patch_path("truncated");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir], Some(&input))?;
+    assert!(res.status.success());
+
+    // Whether the last patch is kept or not.
+    for args in [&["--strict"][..], &["--strict", "--glob", "fresh.txt"]] {
+        let res = spatch(&[&["-o", dir], args].concat(), Some(&input))?;
+        assert!(!res.status.success());
+        let stderr = String::from_utf8(res.stderr)?;
+        assert!(stderr.contains("ends in the middle of hunk '@@ -1,10 +1,11 @@'"));
+        assert!(stderr.contains("has -7 +7. It looks truncated"));
+    }
+
+    let res = spatch(
+        &["-o", dir, "--strict"],
+        Some(&test_patch_path("reversible")),
+    )?;
+    assert!(res.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_keep_relative() -> anyhow::Result<()> {
+    let input = test_patch_path("relative");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir, "--keep-relative"], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(
+        file_names(out.path())?,
+        [".-local.txt.patch", "..-sibling-file.txt.patch"]
+    );
+
+    // Kept directories must not lead out of the output directory.
+    let nested = out.path().join("nested");
+    let nested = nested.to_str().unwrap();
+    let res = spatch(
+        &["-o", nested, "--keep-relative", "--preserve-dirs"],
+        Some(&input),
+    )?;
+    assert!(!res.status.success());
+    assert!(
+        String::from_utf8(res.stderr)?.contains("would be written outside the output directory")
+    );
+    assert!(!out.path().join("sibling").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_repeated_file_line() -> anyhow::Result<()> {
+    let input = test_patch_path("doubled_file_line");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().to_str().unwrap();
+
+    let res = spatch(&["-o", dir], Some(&input))?;
+    assert!(res.status.success());
+    let stderr = String::from_utf8(res.stderr)?;
+    assert!(stderr.contains("two.txt: the header has more than one '+++' line"));
+
+    let res = spatch(&["-o", dir, "--strict", "--glob", "fine.txt"], Some(&input))?;
+    assert!(!res.status.success());
+    let stderr = String::from_utf8(res.stderr)?;
+    assert!(stderr.contains("1 patches name their file more than once"));
+
+    Ok(())
+}
+
+#[test]
+fn test_lenient() -> anyhow::Result<()> {
+    let input = test_patch_path("leading_fragment");
+    let out = tempfile::tempdir()?;
+    let dir = out.path().join("out");
+
+    let res = spatch(&["-o", dir.to_str().unwrap()], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(&dir)?, ["README.patch"]);
+
+    let dir = out.path().join("lenient");
+    let res = spatch(&["-o", dir.to_str().unwrap(), "--lenient"], Some(&input))?;
+    assert!(res.status.success());
+    assert_eq!(file_names(&dir)?, ["README.patch", "src-lib.rs.patch"]);
+    let fragment = fs::read_to_string(dir.join("src-lib.rs.patch"))?;
+    let expected = fs::read_to_string(&input)?;
+    assert_eq!(fragment, expected[..expected.find("diff --git").unwrap()]);
+
+    Ok(())
+}
+
+#[test]
+fn test_name_from() -> anyhow::Result<()> {
+    let input = test_patch_path("naming");
+    let names = |renamed: &'static str| {
+        let mut names = vec!["docs-new.md.patch", "docs-old.md.patch", "src-lib.rs.patch"];
+        names.push(renamed);
+        names.sort();
+        names
+    };
+    for (mode, expected) in [
+        (None, names("src-b.rs.patch")),
+        (Some("auto"), names("src-b.rs.patch")),
+        (Some("new"), names("src-b.rs.patch")),
+        // Added files have nothing but their new path to go by.
+        (Some("old"), names("src-a.rs.patch")),
+    ] {
+        let out = tempfile::tempdir()?;
+        let dir = out.path().to_str().unwrap();
+        let mut args = vec!["-o", dir];
+        args.extend(mode.map(|mode| ["--name-from", mode]).iter().flatten());
+        let res = spatch(&args, Some(&input))?;
+        assert!(res.status.success(), "{:?}", mode);
+        assert_eq!(file_names(out.path())?, expected, "{:?}", mode);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_svn() -> anyhow::Result<()> {
+    let input = test_patch_path("svn");
+    let out = tempfile::tempdir()?;
+
+    let res = spatch(&["-o", out.path().to_str().unwrap()], Some(&input))?;
+    assert!(res.status.success());
+    let names = file_names(out.path())?;
+    assert_eq!(
+        names,
+        ["trunk-docs-notes.txt.patch", "trunk-src-main.c.patch"]
+    );
+    let written = fs::read_to_string(out.path().join(&names[1]))?
+        + &fs::read_to_string(out.path().join(&names[0]))?;
+    assert_eq!(written, fs::read_to_string(&input)?);
+
+    let res = spatch(&["--list"], Some(&input))?;
+    assert_eq!(
+        String::from_utf8(res.stdout)?,
+        "M trunk/src/main.c\nA trunk/docs/notes.txt\n"
+    );
+
+    Ok(())
+}
@@ -1,7 +1,9 @@
 use std::path::PathBuf;
 
-use anyhow;
-use spatch::diff_parser::DiffParser;
+use spatch::diff_parser::{
+    DiffParser, Encoding, ExtendedHeader, Hunk, OwnedPatch, PatchMeta, Segment, SendDiffParser,
+    Side, decode_binary_payload, parse_hunk_header,
+};
 
 fn test_patch_path(name: &str) -> PathBuf {
     PathBuf::from(env!("CARGO_MANIFEST_DIR"))
@@ -18,7 +20,7 @@ fn test_parse_simple_patch_and_footer() -> anyhow::Result<()> {
     {
         let mut patch = dp.next().expect("one patch");
         assert!(patch.new_filename().is_some());
-        assert!(patch.new_filename().as_ref().unwrap() == "a/file.txt");
+        assert!(patch.new_path() == Some("a/file.txt"));
 
         let lines: Vec<String> = patch.lines().collect();
         // expect header/hunk header and hunk body lines present; footer is not
@@ -54,9 +56,9 @@ fn test_hunk_count_mismatch_includes_extra_body_lines() -> anyhow::Result<()> {
 #[test]
 fn test_multiple_patches_and_non_git_input() -> anyhow::Result<()> {
     let p = test_patch_path("multi");
-    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+    let dp = DiffParser::new(std::fs::File::open(&p)?);
     let mut list = Vec::new();
-    while let Some(patch) = dp.next() {
+    for patch in dp {
         let f = patch.new_filename();
         assert!(f.is_some());
         list.push(f.to_owned().unwrap());
@@ -73,16 +75,214 @@ fn test_multiple_patches_and_non_git_input() -> anyhow::Result<()> {
 
 #[test]
 fn test_malformed_hunk_header_does_not_panic() -> anyhow::Result<()> {
-    // malformed hunk header without '+' token -> parse_new_hunk_len returns None
+    // malformed hunk header without '+' token -> parse_hunk_header returns None
     let p = test_patch_path("malformed");
     let mut dp = DiffParser::new(std::fs::File::open(&p)?);
     let mut patch = dp.next().expect("patch present");
     let lines: Vec<String> = patch.lines().collect();
-    // header may or may not have been parsed as a hunk header; ensure we
-    // did not panic and the iterator completed. Accept either an empty
-    // body or a body that contains a hunk header.
-    let has_hunk = lines.iter().any(|l| l.trim_start().starts_with("@@"));
-    let _ = has_hunk; // just ensure evaluation
+    // The body stops at the header, which is kept around to report it.
+    assert!(lines.is_empty());
+    assert!(patch.hunks().is_empty());
+    assert_eq!(patch.malformed_hunk(), Some("@@ -1,2 1,2 @@"));
+    assert_eq!(patch.into_owned().malformed_hunk(), Some("@@ -1,2 1,2 @@"));
+    Ok(())
+}
+
+#[test]
+fn test_parse_hunk_header() {
+    let hunk = parse_hunk_header("@@ -56,7 +57,8 @@ fn main() {\r").expect("hunk");
+    assert_eq!((hunk.old_start(), hunk.old_len()), (56, 7));
+    assert_eq!((hunk.new_start(), hunk.new_len()), (57, 8));
+    assert_eq!(hunk.context_heading(), Some("fn main() {"));
+    assert_eq!(hunk.header(), "@@ -56,7 +57,8 @@ fn main() {");
+    assert_eq!(hunk.raw(), "@@ -56,7 +57,8 @@ fn main() {\r");
+    assert_eq!(hunk.body_len(), (0, 0));
+
+    let hunk = parse_hunk_header("@@ -0,0 +1 @@").expect("hunk");
+    assert_eq!(
+        (hunk.old_len(), hunk.new_start(), hunk.new_len()),
+        (0, 1, 1)
+    );
+    assert!(hunk.is_at_file_start());
+
+    for line in ["@@ -1,2 1,2 @@", "@@ -a +b @@", "diff --git a/x b/x", ""] {
+        assert_eq!(parse_hunk_header(line), None, "{}", line);
+    }
+}
+
+#[test]
+fn test_glued_hunk_heading() -> anyhow::Result<()> {
+    let p = test_patch_path("glued_heading");
+    let patch = DiffParser::new(std::fs::File::open(&p)?)
+        .next()
+        .expect("patch")
+        .into_owned();
+
+    let hunks = patch.hunks();
+    assert_eq!(hunks.len(), 2);
+    assert!(hunks.iter().all(Hunk::is_consistent));
+    assert_eq!((hunks[1].old_start(), hunks[1].new_len()), (10, 3));
+    assert_eq!(hunks[0].section(), "import os");
+    assert_eq!(hunks[0].context_heading(), Some("import os"));
+    // Only git's separator is dropped, the method keeps its indentation.
+    assert_eq!(hunks[1].section(), "    def run(self):");
+    assert_eq!(hunks[1].context_heading(), Some("   def run(self):"));
+
+    let hunk = parse_hunk_header("@@ -1 +1 @@  \r").expect("hunk");
+    assert_eq!(hunk.context_heading(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_loose_hunk_headers() -> anyhow::Result<()> {
+    let p = test_patch_path("loose_hunk_header");
+    let mut dp = DiffParser::new(std::fs::File::open(&p)?).into_owned_patches();
+
+    let patch = dp.next().expect("patch");
+    assert_eq!(patch.lines().len(), 7);
+    assert_eq!(patch.malformed_hunk(), None);
+    let hunks = patch.hunks();
+    assert_eq!(hunks.len(), 2);
+    assert!(hunks.iter().all(Hunk::is_consistent));
+    assert_eq!(hunks[0].section(), "int main(void)");
+    assert_eq!((hunks[1].old_start(), hunks[1].new_start()), (10, 11));
+    assert_eq!(hunks[1].raw(), "@@ -10 +11");
+    assert_eq!(hunks[1].section(), "");
+    assert_eq!(hunks[0].context_heading(), Some("int main(void)"));
+    assert_eq!(hunks[1].context_heading(), None);
+
+    let patch = dp.next().expect("second patch");
+    assert_eq!(patch.new_path(), Some("util.c"));
+    assert_eq!(patch.hunks()[0].new_len(), 2);
+    assert_eq!(patch.hunks()[0].section(), " static void helper(void)");
+    assert_eq!(
+        patch.hunks()[0].context_heading(),
+        Some("static void helper(void)")
+    );
+    assert!(patch.hunks()[0].is_consistent());
+
+    assert!(dp.next().is_none());
+    Ok(())
+}
+
+#[test]
+fn test_encodings() -> anyhow::Result<()> {
+    let p = test_patch_path("latin1");
+    let open = |encoding| -> anyhow::Result<_> {
+        Ok(DiffParser::with_encoding(
+            std::fs::File::open(&p)?,
+            encoding,
+        ))
+    };
+
+    // Parsing stops at the Latin-1 line, in the middle of the first body.
+    let mut dp = open(Encoding::Utf8Strict)?;
+    let patch = dp.next().expect("patch").into_owned();
+    assert_eq!(patch.lines(), ["@@ -1,3 +1,3 @@"]);
+    assert!(dp.next().is_none());
+    assert_eq!(dp.invalid_line(), Some(6));
+
+    let mut dp = open(Encoding::Utf8Lossy)?;
+    let patch = dp.next().expect("patch").into_owned();
+    assert_eq!(patch.lines()[1], " caf\u{FFFD}");
+    assert_eq!(
+        dp.next().expect("second patch").new_path(),
+        Some("other.txt")
+    );
+    assert_eq!(dp.invalid_line(), None);
+
+    let mut dp = open(Encoding::Bytes)?;
+    let patch = dp.next().expect("patch").into_owned();
+    assert_eq!(patch.lines()[1], " caf\u{e9}");
+    assert_eq!(Encoding::Bytes.encode(&patch.lines()[1]), &b" caf\xe9"[..]);
+    assert!(patch.hunks()[0].is_consistent());
+    assert_eq!(
+        dp.next().expect("second patch").new_path(),
+        Some("other.txt")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_stale_patch_reads_nothing() -> anyhow::Result<()> {
+    let p = test_patch_path("multi_hunk");
+    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+
+    let mut first = dp.next().expect("patch");
+    assert_eq!(first.lines().next().as_deref(), Some("@@ -1,3 +1,3 @@"));
+    let mut second = dp.next().expect("second patch");
+    // The rest of the first body was skipped, not handed to the wrong patch.
+    assert_eq!(first.lines().count(), 0);
+    assert_eq!(first.hunks().len(), 1);
+    assert_eq!(second.new_path(), Some("README"));
+    assert_eq!(
+        second.lines().collect::<Vec<_>>(),
+        ["@@ -1 +1 @@", "-hello", "+hello world"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_undrained_patch_of_patches() -> anyhow::Result<()> {
+    let p = test_patch_path("patch_of_patches");
+    let drained: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .map(|p| (p.new_path().map(str::to_string), p.header().to_string()))
+        .collect();
+
+    // Leave the bodies unread, or stop reading them at every possible line,
+    // nested `+diff --git` ones included.
+    for read in 0..12 {
+        let undrained: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+            .map(|mut p| {
+                p.lines().take(read).for_each(drop);
+                (p.new_path().map(str::to_string), p.header().to_string())
+            })
+            .collect();
+        assert_eq!(undrained, drained, "read {} lines", read);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_no_newline_marker_belongs_to_hunk() -> anyhow::Result<()> {
+    let p = test_patch_path("no_newline");
+    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+
+    let mut patch = dp.next().expect("patch");
+    let lines: Vec<String> = patch.lines().collect();
+    assert_eq!(
+        lines.last().map(String::as_str),
+        Some("\\ No newline at end of file")
+    );
+
+    let patch = dp.next().expect("second patch");
+    assert_eq!(patch.new_path(), Some("other.txt"));
+    Ok(())
+}
+
+#[test]
+fn test_crlf_input() -> anyhow::Result<()> {
+    let p = test_patch_path("crlf");
+    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+
+    let mut patch = dp.next().expect("text patch");
+    assert_eq!(patch.old_path(), Some("win.txt"));
+    assert_eq!(patch.new_path(), Some("win.txt"));
+    let lines: Vec<String> = patch.lines().collect();
+    assert_eq!(lines, ["@@ -1,2 +1,2 @@\r", " first\r", "-old\r", "+new\r"]);
+    assert_eq!(patch.hunks()[0].header(), "@@ -1,2 +1,2 @@");
+    assert_eq!(patch.hunks()[0].raw(), "@@ -1,2 +1,2 @@\r");
+
+    let patch = dp.next().expect("binary patch");
+    assert_eq!(patch.new_path(), Some("pic.png"));
+    assert!(patch.is_binary());
+
+    assert!(dp.next().is_none());
     Ok(())
 }
 
@@ -91,9 +291,10 @@ fn test_binary_diff_simple() -> anyhow::Result<()> {
     let p = test_patch_path("binary_simple");
     let mut dp = DiffParser::new(std::fs::File::open(&p)?);
     let patch = dp.next().expect("binary patch");
-    assert!(patch.new_filename().as_ref().unwrap() == "image.png");
+    assert!(patch.new_path() == Some("image.png"));
 
     // Binary diffs should have the header but no hunk content
+    assert!(patch.is_binary());
     assert!(patch.header().contains("Binary files"));
 
     Ok(())
@@ -104,9 +305,9 @@ fn test_binary_diff_modified() -> anyhow::Result<()> {
     let p = test_patch_path("binary_modified");
     let mut dp = DiffParser::new(std::fs::File::open(&p)?);
     let patch = dp.next().expect("binary patch");
-    assert!(patch.new_filename().as_ref().unwrap() == "photo.jpg");
+    assert!(patch.new_path() == Some("photo.jpg"));
 
-    assert!(patch.header().contains("Binary files"));
+    assert!(patch.is_binary());
 
     Ok(())
 }
@@ -116,10 +317,10 @@ fn test_binary_diff_deleted() -> anyhow::Result<()> {
     let p = test_patch_path("binary_deleted");
     let mut dp = DiffParser::new(std::fs::File::open(&p)?);
     let patch = dp.next().expect("binary patch");
-    assert!(patch.old_filename().as_ref().unwrap() == "old_binary.bin");
-    assert!(patch.new_filename().is_none());
+    assert!(patch.old_path() == Some("old_binary.bin"));
+    assert!(patch.new_path().is_none());
 
-    assert!(patch.header().contains("Binary files"));
+    assert!(patch.is_binary());
 
     Ok(())
 }
@@ -127,11 +328,13 @@ fn test_binary_diff_deleted() -> anyhow::Result<()> {
 #[test]
 fn test_mixed_text_and_binary_patches() -> anyhow::Result<()> {
     let p = test_patch_path("mixed_patches");
-    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+    let dp = DiffParser::new(std::fs::File::open(&p)?);
 
     let mut patches = Vec::new();
-    while let Some(patch) = dp.next() {
-        patches.push(patch.new_filename().as_ref().unwrap().to_string());
+    for patch in dp {
+        let name = patch.new_path().unwrap().to_string();
+        assert_eq!(patch.is_binary(), name == "data.bin");
+        patches.push(name);
     }
 
     assert_eq!(
@@ -151,9 +354,22 @@ fn test_binary_diff_with_mode_change() -> anyhow::Result<()> {
     let p = test_patch_path("binary_mode_change");
     let mut dp = DiffParser::new(std::fs::File::open(&p)?);
     let patch = dp.next().expect("binary patch with mode change");
-    assert!(patch.new_filename().as_ref().unwrap() == "script.sh");
+    assert!(patch.new_path() == Some("script.sh"));
 
-    assert!(patch.header().contains("Binary files"));
+    assert!(patch.is_binary());
+    assert_eq!(
+        patch.extended_headers(),
+        [
+            ExtendedHeader::OldMode("100644".into()),
+            ExtendedHeader::NewMode("100755".into()),
+            ExtendedHeader::Index {
+                old: "aaa1111".into(),
+                new: "bbb2222".into(),
+                mode: None,
+            },
+            ExtendedHeader::Binary,
+        ]
+    );
 
     Ok(())
 }
@@ -161,16 +377,16 @@ fn test_binary_diff_with_mode_change() -> anyhow::Result<()> {
 #[test]
 fn test_multiple_binary_diffs() -> anyhow::Result<()> {
     let p = test_patch_path("multiple_binaries");
-    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+    let dp = DiffParser::new(std::fs::File::open(&p)?);
 
     let mut patches = Vec::new();
-    while let Some(patch) = dp.next() {
+    for patch in dp {
         patches.push((
             patch.old_filename().to_owned(),
             patch.new_filename().to_owned(),
         ));
 
-        assert!(patch.header().contains("Binary files"));
+        assert!(patch.is_binary());
     }
 
     assert_eq!(patches.len(), 3, "should parse three binary patches");
@@ -196,15 +412,89 @@ fn test_multiple_binary_diffs() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_git_binary_patch_payload() -> anyhow::Result<()> {
+    let p = test_patch_path("git_binary_patch");
+    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+
+    let mut patch = dp.next().expect("modified binary patch");
+    assert_eq!(patch.new_path(), Some("blob.bin"));
+    assert!(patch.is_binary());
+    assert!(patch.header().ends_with("GIT binary patch\n"));
+    assert_eq!(
+        patch.binary_payload(),
+        Some(concat!(
+            "literal 11\n",
+            "ScmZQzWKPP=ODwAV#{d8i#{-A}\n",
+            "\n",
+            "literal 10\n",
+            "RcmZQzWJ=1+ODwAV4*(1}1Bd_s\n",
+            "\n",
+        ))
+    );
+    assert_eq!(patch.lines().count(), 0);
+
+    let mut patch = dp.next().expect("text patch");
+    assert_eq!(patch.new_path(), Some("keep.txt"));
+    assert!(!patch.is_binary());
+    assert!(patch.binary_payload().is_none());
+    assert_eq!(patch.lines().count(), 3);
+
+    let patch = dp.next().expect("added binary patch");
+    assert_eq!(patch.new_path(), Some("new.bin"));
+    assert!(patch.binary_payload().unwrap().starts_with("literal 7\n"));
+    assert!(
+        patch
+            .binary_payload()
+            .unwrap()
+            .ends_with("literal 0\nHcmV?d00001\n\n")
+    );
+
+    assert!(dp.next().is_none());
+    Ok(())
+}
+
+#[test]
+fn test_decode_binary() -> anyhow::Result<()> {
+    let p = test_patch_path("binary_literal");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+    assert_eq!(patches.len(), 3);
+
+    let all_bytes: Vec<u8> = (0..=255).chain(0..=255).collect();
+    assert_eq!(patches[0].decode_binary(), Some(all_bytes));
+    // Removed files come from the second block.
+    assert_eq!(
+        patches[1].decode_binary().as_deref(),
+        Some(&b"\x00\x01\x02old binary\xff\xfe"[..])
+    );
+    let payload = patches[1].binary_payload().unwrap();
+    assert_eq!(decode_binary_payload(payload, false), Some(Vec::new()));
+    assert_eq!(patches[2].decode_binary(), None);
+
+    let p = test_patch_path("binary_simple");
+    let patch = DiffParser::new(std::fs::File::open(&p)?).next().unwrap();
+    assert!(patch.is_binary());
+    assert_eq!(patch.decode_binary(), None);
+
+    assert_eq!(
+        decode_binary_payload("delta 12\nHcmV?d00001\n\n", false),
+        None
+    );
+    assert_eq!(decode_binary_payload("literal 15\nW~~~~~\n\n", false), None);
+
+    Ok(())
+}
 #[test]
 fn test_patch_of_patch_files() -> anyhow::Result<()> {
     // Test parsing a diff that modifies .patch files themselves
     let p = test_patch_path("patch_of_patches");
-    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+    let dp = DiffParser::new(std::fs::File::open(&p)?);
 
     let mut patches = Vec::new();
-    while let Some(mut patch) = dp.next() {
-        let filename = patch.new_filename().as_ref().unwrap().to_string();
+    for mut patch in dp {
+        let filename = patch.new_path().unwrap().to_string();
         let header = patch.header().to_string();
         let lines: Vec<String> = patch.lines().collect();
         patches.push((filename, header, lines));
@@ -310,3 +600,1033 @@ fn test_patch_of_patch_files() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_hunk_body_lengths() -> anyhow::Result<()> {
+    let p = test_patch_path("mismatch");
+    let mut patch = DiffParser::new(std::fs::File::open(&p)?)
+        .next()
+        .expect("patch");
+    patch.lines().for_each(drop);
+    let hunks = patch.hunks();
+    assert_eq!(hunks.len(), 1);
+    assert_eq!(hunks[0].header(), "@@ -1,1 +1,1 @@");
+    assert_eq!((hunks[0].old_len(), hunks[0].new_len()), (1, 1));
+    assert_eq!(hunks[0].body_len(), (0, 1));
+    assert!(!hunks[0].is_consistent());
+
+    let p = test_patch_path("multi_hunk");
+    for mut patch in DiffParser::new(std::fs::File::open(&p)?) {
+        patch.lines().for_each(drop);
+        assert!(!patch.hunks().is_empty());
+        assert!(patch.hunks().iter().all(|h| h.is_consistent()));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_owned_patches_hunk_count() -> anyhow::Result<()> {
+    let p = test_patch_path("multi_hunk");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+
+    let counts: Vec<_> = patches
+        .iter()
+        .map(|p| (p.new_path().unwrap(), p.hunk_count()))
+        .collect();
+    assert_eq!(counts, [("src/lib.rs", 2), ("README", 1)]);
+    assert!(patches[0].lines()[0].starts_with("@@ -"));
+
+    Ok(())
+}
+
+#[test]
+fn test_no_prefix() -> anyhow::Result<()> {
+    let p = test_patch_path("no_prefix");
+    let names: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .map(|p| (p.old_filename().clone(), p.new_filename().clone()))
+        .collect();
+    let some = |s: &str| Some(s.to_string());
+    assert_eq!(
+        names,
+        [
+            (some("a/file.txt"), some("a/file.txt")),
+            (some("src/x.rs"), some("src/x.rs")),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_paths_with_spaces() -> anyhow::Result<()> {
+    let p = test_patch_path("spaces");
+    let names: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .map(|p| (p.old_filename().clone(), p.new_filename().clone()))
+        .collect();
+    let some = |s: &str| Some(s.to_string());
+    assert_eq!(
+        names,
+        [
+            (some("foo bar.txt"), some("foo bar.txt")),
+            (some("old name.txt"), some("new name.txt")),
+            // `diff --git a/x b/y b/z` alone could be read either way.
+            (some("x b/y"), some("z")),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_mode_only_change() -> anyhow::Result<()> {
+    let p = test_patch_path("mode_only");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+    assert_eq!(patches.len(), 2);
+
+    assert!(!patches[0].is_mode_only_change());
+    assert!(patches[1].is_mode_only_change());
+    assert_eq!(patches[1].old_path(), Some("run.sh"));
+    assert_eq!(patches[1].new_path(), Some("run.sh"));
+    assert!(patches[1].lines().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_adjacent_empty_diffs() -> anyhow::Result<()> {
+    let p = test_patch_path("adjacent_empty");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+    assert_eq!(patches.len(), 3);
+
+    for (patch, name) in patches.iter().zip(["empty.txt", "other.txt"]) {
+        assert_eq!(patch.old_path(), Some(name));
+        assert_eq!(patch.new_path(), Some(name));
+        assert_eq!(patch.header(), format!("diff --git a/{0} b/{0}\n", name));
+        assert!(patch.lines().is_empty());
+    }
+    assert_eq!(patches[2].lines(), ["@@ -1 +1 @@", "-x", "+y"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_inflated_hunk_count() -> anyhow::Result<()> {
+    let p = test_patch_path("inflated_count");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+    assert_eq!(patches.len(), 2);
+
+    // The next diff ends the hunk, and so does the end of the input.
+    assert_eq!(patches[0].lines(), ["@@ -1,4000000000 +1,1 @@", "-a", "+b"]);
+    assert_eq!(
+        patches[1].lines(),
+        [
+            "@@ -1 +1 @@",
+            "-x",
+            "+y",
+            "@@ -5,4000000000 +5,4000000000 @@",
+            " context"
+        ]
+    );
+    let body_lens: Vec<_> = patches
+        .iter()
+        .flat_map(|p| p.hunks())
+        .map(|h| h.body_len())
+        .collect();
+    assert_eq!(body_lens, [(1, 1), (1, 1), (1, 1)]);
+    assert!(!patches[0].hunks()[0].is_consistent());
+
+    Ok(())
+}
+
+#[test]
+fn test_from_files() -> anyhow::Result<()> {
+    let paths = [
+        test_patch_path("multi"),
+        test_patch_path("does_not_exist"),
+        test_patch_path("mode_only"),
+    ];
+    let mut patches = DiffParser::from_files(&paths);
+
+    let mut next = || match patches.next() {
+        Some(Ok((path, patch))) => Some(Ok((path, patch.new_path().map(str::to_string)))),
+        Some(Err(e)) => Some(Err(e.kind())),
+        None => None,
+    };
+    let some = |path: &PathBuf, name: &str| Some(Ok((path.clone(), Some(name.to_string()))));
+    assert_eq!(next(), some(&paths[0], "one"));
+    assert_eq!(next(), some(&paths[0], "two"));
+    assert_eq!(next(), Some(Err(std::io::ErrorKind::NotFound)));
+    assert_eq!(next(), some(&paths[2], "a.txt"));
+    assert_eq!(next(), some(&paths[2], "run.sh"));
+    assert_eq!(next(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_from_str() {
+    let mut dp =
+        DiffParser::from_str("diff --git a/f b/f\n--- a/f\n+++ b/f\n@@ -1 +1 @@\n-old\n+new\n");
+    let mut patch = dp.next().expect("patch");
+    assert_eq!(patch.new_path(), Some("f"));
+    assert_eq!(
+        patch.lines().collect::<Vec<_>>(),
+        ["@@ -1 +1 @@", "-old", "+new"]
+    );
+    assert!(dp.next().is_none());
+}
+
+#[test]
+fn test_rename_extended_headers() {
+    let mut dp = DiffParser::from_str(
+        "diff --git a/old.rs b/new.rs\n\
+         similarity index 90%\n\
+         rename from old.rs\n\
+         rename to new.rs\n\
+         index 1234567..89abcde 100644\n\
+         --- a/old.rs\n\
+         +++ b/new.rs\n\
+         @@ -1 +1 @@\n\
+         -old\n\
+         +new\n",
+    );
+    let patch = dp.next().expect("patch");
+    assert_eq!(
+        patch.extended_headers(),
+        [
+            ExtendedHeader::SimilarityIndex(90),
+            ExtendedHeader::RenameFrom("old.rs".into()),
+            ExtendedHeader::RenameTo("new.rs".into()),
+            ExtendedHeader::Index {
+                old: "1234567".into(),
+                new: "89abcde".into(),
+                mode: Some("100644".into()),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_diff_git_only_paths() {
+    // Neither patch has `---`/`+++` lines, the diff --git line is all there is.
+    let mut dp = DiffParser::from_str(
+        "diff --git a/a/lib/a/thing.rs b/a/lib/a/thing.rs\n\
+         old mode 100644\n\
+         new mode 100755\n\
+         diff --git a/lib/a/old.rs b/lib/b/new.rs\n\
+         similarity index 100%\n\
+         rename from lib/a/old.rs\n\
+         rename to lib/b/new.rs\n",
+    );
+
+    // Only the leading prefixes go, however often a/ shows up in the path.
+    let mode = dp.next().expect("mode change");
+    assert_eq!(mode.old_path(), Some("a/lib/a/thing.rs"));
+    assert_eq!(mode.new_path(), Some("a/lib/a/thing.rs"));
+    assert!(mode.is_mode_only_change());
+
+    let rename = dp.next().expect("rename");
+    assert_eq!(rename.old_path(), Some("lib/a/old.rs"));
+    assert_eq!(rename.new_path(), Some("lib/b/new.rs"));
+    assert!(dp.next().is_none());
+}
+
+#[test]
+fn test_tab_separated_diff_git_paths() -> anyhow::Result<()> {
+    let p = test_patch_path("tab_separated");
+    let paths: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .map(|p| {
+            (
+                p.old_path().map(str::to_string),
+                p.new_path().map(str::to_string),
+            )
+        })
+        .collect();
+    let both = |old: &str, new: &str| (Some(old.to_string()), Some(new.to_string()));
+    assert_eq!(
+        paths,
+        [
+            both("edit.txt", "edit.txt"),
+            both("my dir/spaced file.txt", "my dir/spaced file.txt"),
+            both("old.txt", "new.txt"),
+            // Only the diff --git line names the file of a mode change.
+            both("run.sh", "run.sh"),
+        ]
+    );
+
+    // However many blanks there are.
+    let patch =
+        DiffParser::from_str("diff --git a/x.sh \t b/x.sh\nold mode 100644\nnew mode 100755\n")
+            .next()
+            .expect("mode change");
+    assert_eq!(patch.new_path(), Some("x.sh"));
+
+    Ok(())
+}
+
+#[test]
+fn test_affects() {
+    let mut dp = DiffParser::from_str(
+        "diff --git a/src/old.rs b/lib/new.rs\n\
+         similarity index 100%\n\
+         rename from src/old.rs\n\
+         rename to lib/new.rs\n\
+         diff --git a/srcs/gone.rs b/srcs/gone.rs\n\
+         deleted file mode 100644\n\
+         index 1234567..0000000\n\
+         --- a/srcs/gone.rs\n\
+         +++ /dev/null\n\
+         @@ -1 +0,0 @@\n\
+         -gone\n",
+    );
+
+    let rename = dp.next().expect("rename").into_owned();
+    assert!(rename.affects("src/old.rs"));
+    assert!(rename.affects("lib/new.rs"));
+    assert!(!rename.affects("src"));
+    assert!(rename.affects_prefix("src"));
+    assert!(rename.affects_prefix("lib/"));
+    assert!(rename.affects_prefix("lib/new.rs"));
+
+    let removal = dp.next().expect("removal");
+    assert!(removal.affects("srcs/gone.rs"));
+    assert!(!removal.affects("/dev/null"));
+    assert!(removal.affects_prefix("srcs"));
+    assert!(!removal.affects_prefix("src"));
+}
+
+#[test]
+fn test_matches_glob_and_regex() -> anyhow::Result<()> {
+    let mut dp = DiffParser::from_str(
+        "diff --git a/src/old.rs b/lib/new.rs\n\
+         similarity index 100%\n\
+         rename from src/old.rs\n\
+         rename to lib/new.rs\n\
+         diff --git a/src/main.rs b/src/main.rs\n\
+         new file mode 100644\n\
+         index 0000000..1234567\n\
+         --- /dev/null\n\
+         +++ b/src/main.rs\n\
+         @@ -0,0 +1 @@\n\
+         +fn main() {}\n",
+    );
+    let glob = |pattern: &str| globset::Glob::new(pattern).map(|g| g.compile_matcher());
+
+    // The new path counts as much as the old one.
+    let rename = dp.next().expect("rename");
+    assert!(!rename.matches_glob(&glob("src/*")?));
+    assert!(!rename.matches_glob(&glob("lib/*")?));
+    assert!(rename.matches_glob(&glob("*.rs")?));
+    assert!(rename.matches_basename_glob(&glob("*.rs")?));
+    assert!(!rename.matches_basename_glob(&glob("new.rs")?));
+    assert!(!rename.matches_regex(&regex::Regex::new("^src/")?));
+    assert!(rename.matches_regex(&regex::Regex::new(r"\.rs$")?));
+
+    let added = dp.next().expect("added file").into_owned();
+    assert!(added.matches_glob(&glob("src/*")?));
+    assert!(added.matches_basename_glob(&glob("main.rs")?));
+    assert!(!added.matches_basename_glob(&glob("src/*")?));
+    assert!(added.matches_regex(&regex::Regex::new("main")?));
+    assert!(!added.matches_regex(&regex::Regex::new("^lib/")?));
+
+    Ok(())
+}
+
+#[test]
+fn test_display_name() -> anyhow::Result<()> {
+    let p = test_patch_path("naming");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+    let names: Vec<_> = patches.iter().map(|p| p.display_name()).collect();
+    assert_eq!(
+        names,
+        [
+            Some("docs/new.md".to_string()),
+            Some("docs/old.md".to_string()),
+            Some("src/b.rs".to_string()),
+            Some("src/lib.rs".to_string()),
+        ]
+    );
+    assert!(
+        patches
+            .iter()
+            .all(|p| p.meta().display_name() == p.display_name())
+    );
+
+    let mut dp = DiffParser::from_str("diff --git a/x b/x\n--- /dev/null\n+++ /dev/null\n");
+    assert_eq!(dp.next().expect("patch").display_name(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_whitespace_only_hunks() -> anyhow::Result<()> {
+    let p = test_patch_path("whitespace_only");
+    let mut patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+    let kinds: Vec<_> = patches.iter().map(|p| p.is_whitespace_only()).collect();
+    assert_eq!(kinds, [false, true, false]);
+
+    // Only the reindented line goes, the real change stays.
+    assert_eq!(patches[0].drop_whitespace_only_hunks(), 1);
+    assert_eq!(patches[0].hunk_count(), 1);
+    assert_eq!(patches[0].lines()[0], "@@ -9,5 +9,5 @@ fn a() {");
+    assert_eq!(patches[0].lines().len(), 7);
+    assert_eq!(patches[2].drop_whitespace_only_hunks(), 0);
+    assert_eq!(patches[2].lines().len(), 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_patch_meta() -> anyhow::Result<()> {
+    let p = test_patch_path("naming");
+    let mut metas = Vec::new();
+    for mut patch in DiffParser::new(std::fs::File::open(&p)?) {
+        metas.push(patch.meta());
+        // Reading the body doesn't change what the patch is about.
+        patch.lines().for_each(drop);
+        assert_eq!(patch.meta(), *metas.last().unwrap());
+        assert_eq!(patch.into_owned().meta(), *metas.last().unwrap());
+    }
+
+    // Metadata outlives the parser and can go to other threads.
+    let metas = std::thread::spawn(move || metas).join().unwrap();
+    let kinds: Vec<_> = metas
+        .iter()
+        .map(|m| (m.new_file, m.deleted_file, m.binary))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (true, false, false),
+            (false, true, false),
+            (false, false, false),
+            (false, false, false)
+        ]
+    );
+    assert_eq!(
+        metas[2],
+        PatchMeta {
+            old_path: Some("src/a.rs".into()),
+            new_path: Some("src/b.rs".into()),
+            header: "diff --git a/src/a.rs b/src/b.rs\n\
+                     similarity index 100%\n\
+                     rename from src/a.rs\n\
+                     rename to src/b.rs\n"
+                .into(),
+            binary: false,
+            new_file: false,
+            deleted_file: false,
+        }
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_hg_diff() -> anyhow::Result<()> {
+    let p = test_patch_path("hg");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+
+    let paths: Vec<_> = patches
+        .iter()
+        .map(|p| (p.old_path(), p.new_path()))
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            (Some("src/main.rs"), Some("src/main.rs")),
+            (None, Some("docs/new file.md")),
+            (Some("old.txt"), None),
+        ]
+    );
+    assert!(
+        patches[0]
+            .header()
+            .starts_with("diff -r 1a2b3c4d5e6f -r 6f5e4d3c2b1a src/main.rs\n")
+    );
+    assert_eq!(
+        patches[0].lines(),
+        ["@@ -1,2 +1,3 @@", " fn main() {", "+    run();", " }"]
+    );
+    assert!(
+        patches
+            .iter()
+            .all(|p| p.hunks().iter().all(Hunk::is_consistent))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_submodule() -> anyhow::Result<()> {
+    let p = test_patch_path("submodule");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+
+    let submodules: Vec<_> = patches
+        .iter()
+        .map(|p| (p.new_path(), p.is_submodule()))
+        .collect();
+    assert_eq!(
+        submodules,
+        [
+            (Some(".gitmodules"), false),
+            (Some("README"), false),
+            (Some("libs/sub"), true),
+            (Some("vendor/tool"), true),
+        ]
+    );
+    assert_eq!(patches[3].old_path(), None);
+    assert_eq!(
+        patches[2].lines()[2],
+        "+Subproject commit dbacf54c2ee55556b03c8fa6a1e1ee80fc5f3699"
+    );
+
+    // Without ---/+++ lines, the paths come from the diff --git line alone.
+    let text = "diff --git a/libs/sub b/libs/sub\n\
+                index cb42920..dbacf54 160000\n\
+                Subproject commit cb42920..dbacf54\n";
+    let mut dp = DiffParser::from_str(text);
+    let patch = dp.next().expect("patch");
+    assert_eq!(patch.new_path(), Some("libs/sub"));
+    assert!(patch.is_submodule());
+    assert!(
+        patch
+            .header()
+            .ends_with("Subproject commit cb42920..dbacf54\n")
+    );
+    assert!(dp.next().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_commit_dates() -> anyhow::Result<()> {
+    let p = test_patch_path("dated_log");
+    let dates: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .map(|p| p.commit_date().map(|d| d.to_rfc3339()))
+        .collect();
+    assert_eq!(
+        dates,
+        [
+            Some("2024-01-15T10:00:00+01:00".to_string()),
+            Some("2024-02-29T23:30:00-05:00".to_string()),
+            Some("2024-03-10T08:00:00+00:00".to_string()),
+        ]
+    );
+
+    // Mails carry RFC 2822 dates, plain diffs none at all.
+    let p = test_patch_path("format_patch");
+    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+    let date = dp.next().expect("patch").commit_date().expect("date");
+    assert_eq!(date.to_rfc3339(), "2026-01-01T00:00:00+00:00");
+    let p = test_patch_path("multi_hunk");
+    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+    assert_eq!(dp.next().expect("patch").commit_date(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_copy() -> anyhow::Result<()> {
+    let p = test_patch_path("copy");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+    assert_eq!(patches.len(), 3);
+
+    let copy = |from: &str, to: &str| Some((from.to_string(), to.to_string()));
+    assert_eq!(patches[0].copy(), copy("src.txt", "edited.txt"));
+    assert_eq!(patches[0].new_path(), Some("edited.txt"));
+    assert_eq!(patches[0].hunk_count(), 1);
+    // An exact copy has no body, and no ---/+++ lines either.
+    assert_eq!(patches[1].copy(), copy("src.txt", "exact.txt"));
+    assert_eq!(patches[1].new_path(), Some("exact.txt"));
+    assert!(patches[1].lines().is_empty());
+    assert_eq!(patches[2].copy(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_format_patch_commit_message() -> anyhow::Result<()> {
+    let p = test_patch_path("format_patch");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+    assert_eq!(patches.len(), 3);
+
+    let first = "Grow one.txt and add two.txt\n\n\
+        The body explains why, at some length, so that the message has more than a subject line.";
+    for patch in &patches[..2] {
+        assert_eq!(patch.commit_message(), Some(first));
+        assert_eq!(patch.author(), Some("Jane Doe <jane@example.com>"));
+    }
+    assert_eq!(patches[2].commit_message(), Some("Grow one.txt again"));
+    assert_eq!(
+        patches[0].commit_hash(),
+        Some("07d1b9f0ea26a66f020e5e7babd666b6683d92fb")
+    );
+    assert_eq!(patches[1].old_path(), None);
+
+    // Plain diffs have no message.
+    let p = test_patch_path("multi_hunk");
+    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+    assert!(dp.next().expect("patch").commit_message().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_hunk_ranges() -> anyhow::Result<()> {
+    let p = test_patch_path("multi_hunk");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+
+    let ranges: Vec<_> = patches
+        .iter()
+        .flat_map(|p| p.hunks())
+        .map(|h| (h.old_start(), h.old_len(), h.new_start(), h.new_len()))
+        .collect();
+    // "@@ -1 +1 @@" leaves the lengths of one line out.
+    assert_eq!(ranges, [(1, 3, 1, 3), (10, 3, 10, 4), (1, 1, 1, 1)]);
+
+    let at_start: Vec<_> = patches
+        .iter()
+        .flat_map(|p| p.hunks())
+        .map(|h| h.is_at_file_start())
+        .collect();
+    assert_eq!(at_start, [true, false, true]);
+
+    let headings: Vec<_> = patches
+        .iter()
+        .flat_map(|p| p.hunks())
+        .map(|h| h.context_heading())
+        .collect();
+    assert_eq!(headings, [None, Some("fn two() {"), None]);
+
+    let lens: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .map(|mut p| (p.old_len(), p.new_len()))
+        .collect();
+    assert_eq!(lens, [(6, 7), (1, 1)]);
+    assert_eq!((patches[0].old_len(), patches[0].new_len()), (6, 7));
+
+    // Counts too big to add up stop at u32::MAX instead of overflowing.
+    let huge = "diff --git a/x b/x\n\
+                --- a/x\n\
+                +++ b/x\n\
+                @@ -1,4000000000 +1,4000000000 @@\n\
+                -a\n\
+                +b\n\
+                @@ -9,4000000000 +9,2 @@\n\
+                -c\n";
+    let mut patch = DiffParser::from_str(huge).next().expect("patch");
+    assert_eq!((patch.old_len(), patch.new_len()), (u32::MAX, 4000000002));
+    let owned = DiffParser::from_str(huge)
+        .next()
+        .expect("patch")
+        .into_owned();
+    assert_eq!((owned.old_len(), owned.new_len()), (u32::MAX, 4000000002));
+
+    Ok(())
+}
+
+#[test]
+fn test_git_log() -> anyhow::Result<()> {
+    let p = test_patch_path("git_log");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+
+    let names: Vec<_> = patches.iter().map(|p| p.new_path().unwrap()).collect();
+    assert_eq!(names, ["one.txt", "two.txt", "one.txt", "two.txt"]);
+    // Commit headers and messages don't end up in the hunks.
+    assert_eq!(patches[1].lines(), ["@@ -1 +1,2 @@", " x", "+y"]);
+
+    assert_eq!(
+        patches[0].commit_message(),
+        Some("Grow both\n\n    indented body line")
+    );
+    assert_eq!(patches[0].author(), Some("Jane Doe <jane@example.com>"));
+    assert_eq!(
+        patches[0].commit_hash(),
+        Some("5d8061e1af4adcc13b3657fe491cc307b9e93ce1")
+    );
+    assert_eq!(
+        patches[3].commit_message(),
+        Some("Grow one.txt and add two.txt")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_rename() -> anyhow::Result<()> {
+    let p = test_patch_path("unrenamed");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+    assert_eq!(patches.len(), 8);
+    let merge = |removed: usize, added: usize| {
+        OwnedPatch::merge_rename(&patches[removed], &patches[added]).map(|p| p.header().to_string())
+    };
+
+    assert_eq!(
+        merge(7, 0).as_deref(),
+        Some(
+            "diff --git a/z_old.txt b/a_new.txt\n\
+             old mode 100644\n\
+             new mode 100755\n\
+             similarity index 100%\n\
+             rename from z_old.txt\n\
+             rename to a_new.txt\n"
+        )
+    );
+    assert!(merge(1, 6).is_some());
+    // Empty files are told apart by their index lines alone.
+    assert!(merge(3, 2).is_some());
+    // Only a removed file and an added one make a rename.
+    assert!(merge(0, 7).is_none());
+    assert!(merge(5, 4).is_none());
+
+    // Without index lines, the bodies have to match.
+    let removed = "diff --git a/x b/x\ndeleted file mode 100644\n\
+                   --- a/x\n+++ /dev/null\n@@ -1 +0,0 @@\n-same\n";
+    let added = "diff --git a/y b/y\nnew file mode 100644\n\
+                 --- /dev/null\n+++ b/y\n@@ -0,0 +1 @@\n+same\n";
+    let other = "diff --git a/z b/z\nnew file mode 100644\n\
+                 --- /dev/null\n+++ b/z\n@@ -0,0 +1 @@\n+other\n";
+    let input = format!("{removed}{added}{other}");
+    let patches: Vec<_> = DiffParser::from_str(&input).into_owned_patches().collect();
+    assert!(OwnedPatch::merge_rename(&patches[0], &patches[1]).is_some());
+    assert!(OwnedPatch::merge_rename(&patches[0], &patches[2]).is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_line_at() {
+    let patch = DiffParser::from_str(
+        "diff --git a/f b/f\n\
+         --- a/f\n\
+         +++ b/f\n\
+         @@ -1,3 +1,3 @@\n\
+         \x20one\n\
+         -two\n\
+         +2\n\
+         \n\
+         @@ -10,2 +10,3 @@ fn ten()\r\n\
+         \x20ten\r\n\
+         +ten and a half\r\n\
+         \x20eleven\r\n\
+         \\ No newline at end of file\n",
+    )
+    .into_owned_patches()
+    .next()
+    .expect("patch");
+
+    assert_eq!(patch.line_at(Side::Old, 2), Some("two"));
+    assert_eq!(patch.line_at(Side::New, 2), Some("2"));
+    // The trimmed context line still counts.
+    assert_eq!(patch.line_at(Side::New, 3), Some(""));
+    assert_eq!(patch.line_at(Side::Old, 11), Some("eleven"));
+    assert_eq!(patch.line_at(Side::New, 11), Some("ten and a half"));
+    assert_eq!(patch.line_at(Side::New, 12), Some("eleven"));
+    // Lines between and past the hunks aren't in the patch.
+    assert_eq!(patch.line_at(Side::New, 4), None);
+    assert_eq!(patch.line_at(Side::Old, 12), None);
+    assert_eq!(patch.line_at(Side::Old, 0), None);
+}
+
+#[test]
+fn test_truncated_hunk() -> anyhow::Result<()> {
+    let p = test_patch_path("truncated");
+    // Read or skipped, the last body falls short.
+    let mut read = DiffParser::new(std::fs::File::open(&p)?);
+    read.by_ref()
+        .for_each(|mut patch| patch.lines().for_each(drop));
+    let mut skipped = DiffParser::new(std::fs::File::open(&p)?);
+    skipped.by_ref().for_each(drop);
+    for parser in [read, skipped] {
+        let hunk = parser.truncated_hunk().expect("truncated");
+        assert_eq!(hunk.header(), "@@ -1,10 +1,11 @@");
+        assert_eq!(hunk.body_len(), (7, 7));
+    }
+
+    // A hunk cut short by the next patch is no truncation.
+    let mut dp = DiffParser::from_str(
+        "diff --git a/a b/a\n\
+         --- a/a\n\
+         +++ b/a\n\
+         @@ -1,3 +1,3 @@\n\
+         -x\n\
+         +y\n\
+         diff --git a/b b/b\n\
+         --- a/b\n\
+         +++ b/b\n\
+         @@ -1 +1 @@\n\
+         -x\n\
+         +y\n",
+    );
+    dp.by_ref().for_each(drop);
+    assert!(dp.truncated_hunk().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_keep_relative() -> anyhow::Result<()> {
+    let p = test_patch_path("relative");
+    let paths = |keep: bool| -> anyhow::Result<Vec<_>> {
+        Ok(DiffParser::new(std::fs::File::open(&p)?)
+            .keep_relative(keep)
+            .map(|patch| patch.new_path().map(str::to_string))
+            .collect())
+    };
+    assert_eq!(
+        paths(false)?,
+        [
+            Some("sibling/file.txt".to_string()),
+            Some("local.txt".to_string())
+        ]
+    );
+    assert_eq!(
+        paths(true)?,
+        [
+            Some("../sibling/file.txt".to_string()),
+            Some("./local.txt".to_string())
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_repeated_file_line() -> anyhow::Result<()> {
+    let p = test_patch_path("doubled_file_line");
+    let mut dp = DiffParser::new(std::fs::File::open(&p)?);
+
+    // The last +++ line wins, as it always did.
+    let doubled = dp.next().expect("doubled");
+    assert_eq!(doubled.repeated_file_line(), Some("+++ b/two.txt"));
+    assert_eq!(doubled.new_path(), Some("two.txt"));
+    let fine = dp.next().expect("fine");
+    assert_eq!(fine.repeated_file_line(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_header_only_patches_have_no_lines() {
+    let next = "diff --git a/next.txt b/next.txt\n\
+                --- a/next.txt\n\
+                +++ b/next.txt\n\
+                @@ -1 +1 @@\n\
+                -old\n\
+                +new\n";
+    let header_only = [
+        // Binary, with and without the payload.
+        "diff --git a/img.png b/img.png\n\
+         index 1111111..2222222 100644\n\
+         Binary files a/img.png and b/img.png differ\n",
+        "diff --git a/img.png b/img.png\n\
+         new file mode 100644\n\
+         index 0000000..e6cbc77\n\
+         GIT binary patch\n\
+         literal 3\n\
+         KcmZQzWMT#Y01f~L\n\
+         \n\
+         literal 0\n\
+         HcmV?d00001\n\
+         \n",
+        // Mode only.
+        "diff --git a/run.sh b/run.sh\n\
+         old mode 100644\n\
+         new mode 100755\n",
+        // Empty files, created and deleted.
+        "diff --git a/empty b/empty\n\
+         new file mode 100644\n\
+         index 0000000..e69de29\n",
+        "diff --git a/empty b/empty\n\
+         deleted file mode 100644\n\
+         index e69de29..0000000\n",
+        // Exact rename.
+        "diff --git a/old.txt b/new.txt\n\
+         similarity index 100%\n\
+         rename from old.txt\n\
+         rename to new.txt\n",
+    ];
+
+    for patch in header_only {
+        let input = format!("{}{}", patch, next);
+        let mut dp = DiffParser::from_str(&input);
+        let mut first = dp.next().expect("header-only patch");
+        assert!(first.lines().next().is_none(), "{}", patch);
+        // Asking again doesn't reach into the next patch either.
+        assert!(first.lines().next().is_none(), "{}", patch);
+        assert!(first.hunks().is_empty());
+
+        let mut second = dp.next().expect("next patch");
+        assert_eq!(second.new_path(), Some("next.txt"));
+        assert_eq!(
+            second.lines().collect::<Vec<_>>(),
+            ["@@ -1 +1 @@", "-old", "+new"]
+        );
+        assert!(dp.next().is_none());
+    }
+}
+
+#[test]
+fn test_segments_give_back_the_input() -> anyhow::Result<()> {
+    // Put back together with the bodies read in full, or only the first line
+    // of each.
+    let rebuild = |input: &str, read_all: bool| {
+        let mut out = String::new();
+        for segment in DiffParser::from_str(input).segments() {
+            match segment {
+                Segment::Preamble(lines) => lines.iter().for_each(|l| out += &format!("{}\n", l)),
+                Segment::Patch(mut patch) => {
+                    out += patch.header();
+                    out += patch.binary_payload().unwrap_or_default();
+                    let lines = patch.lines().take(if read_all { usize::MAX } else { 1 });
+                    lines.for_each(|l| out += &format!("{}\n", l));
+                }
+            }
+        }
+        out
+    };
+
+    for entry in std::fs::read_dir(test_patch_path("").parent().unwrap())? {
+        let Ok(input) = std::fs::read_to_string(entry?.path()) else {
+            continue;
+        };
+        for read_all in [true, false] {
+            let mut expected = input.clone();
+            if !expected.is_empty() && !expected.ends_with('\n') {
+                expected.push('\n');
+            }
+            assert_eq!(rebuild(&input, read_all), expected);
+        }
+    }
+
+    // Past a diff line that names no files, the rest is kept as it is.
+    let input = "Subject: odd\n\ndiff --git\n--- a/x\n+++ b/x\n";
+    let segments: Vec<_> = DiffParser::from_str(input).segments().collect();
+    assert!(matches!(&segments[..], [Segment::Preamble(lines)] if lines.len() == 5));
+
+    Ok(())
+}
+
+#[test]
+fn test_send_diff_parser() -> anyhow::Result<()> {
+    let p = test_patch_path("git_log");
+    let expected: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .map(|patch| (patch.header().to_string(), patch.lines().to_vec()))
+        .collect();
+
+    let parser = SendDiffParser::new(std::fs::File::open(&p)?);
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || parser.for_each(|patch| tx.send(patch).unwrap()))
+        .join()
+        .unwrap();
+    let patches: Vec<_> = rx
+        .iter()
+        .map(|patch| (patch.header().to_string(), patch.lines().to_vec()))
+        .collect();
+    assert!(!patches.is_empty());
+    assert_eq!(patches, expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_lenient_leading_fragment() -> anyhow::Result<()> {
+    let p = test_patch_path("leading_fragment");
+    let paths = |parser: DiffParser<std::fs::File>| {
+        parser
+            .into_owned_patches()
+            .map(|patch| patch.display_name().unwrap_or_default())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(paths(DiffParser::new(std::fs::File::open(&p)?)), ["README"]);
+    let lenient = DiffParser::new(std::fs::File::open(&p)?).lenient(true);
+    assert_eq!(paths(lenient), ["src/lib.rs", "README"]);
+
+    let input = format!("\n{}", std::fs::read_to_string(&p)?);
+    let fragment = DiffParser::from_str(&input)
+        .lenient(true)
+        .next()
+        .expect("fragment");
+    assert_eq!(fragment.header(), "--- a/src/lib.rs\n+++ b/src/lib.rs\n");
+    assert_eq!(fragment.old_path(), Some("src/lib.rs"));
+    assert_eq!(fragment.new_path(), Some("src/lib.rs"));
+    let fragment = fragment.into_owned();
+    assert_eq!(fragment.lines().len(), 5);
+    assert!(fragment.hunks()[0].is_consistent());
+
+    // Only the very first line counts, text before it makes it a preamble.
+    let input = format!("Look at this:\n{}", std::fs::read_to_string(&p)?);
+    let parser = DiffParser::from_str(&input).lenient(true);
+    assert_eq!(parser.count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_svn_index_headers() -> anyhow::Result<()> {
+    let p = test_patch_path("svn");
+    let patches: Vec<_> = DiffParser::new(std::fs::File::open(&p)?)
+        .into_owned_patches()
+        .collect();
+    assert_eq!(patches.len(), 2);
+
+    let modified = &patches[0];
+    assert_eq!(modified.old_path(), Some("trunk/src/main.c"));
+    assert_eq!(modified.new_path(), Some("trunk/src/main.c"));
+    assert!(!modified.has_prefixes());
+    assert!(
+        modified
+            .header()
+            .starts_with("Index: trunk/src/main.c\n=====")
+    );
+    assert!(
+        modified
+            .header()
+            .ends_with("+++ trunk/src/main.c\t(working copy)\n")
+    );
+    assert_eq!(modified.lines().len(), 9);
+    assert!(modified.hunks()[0].is_consistent());
+
+    // A side that doesn't exist is marked as such rather than /dev/null.
+    let added = &patches[1];
+    assert_eq!(added.old_path(), None);
+    assert_eq!(added.new_path(), Some("trunk/docs/notes.txt"));
+    assert_eq!(added.lines().len(), 3);
+
+    // An `Index:` line without its line of `=` is just text.
+    let parser =
+        DiffParser::from_str("Index: trunk/a\n--- trunk/a\n+++ trunk/a\n@@ -1 +1 @@\n-a\n+b\n");
+    assert_eq!(parser.count(), 0);
+
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use spatch::diff_parser::DiffParser;
+use spatch::splitter::{
+    self, ExtractMode, FileProcessing, FilterType, PatchWriter, SplitCount, SplitOptions,
+};
+
+fn test_patch_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("test_patches")
+        .join(format!("{}.patch", name))
+}
+
+#[test]
+fn test_split_into_directory() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let options = SplitOptions {
+        filter: FilterType::Glob(globset::Glob::new("src/*.rs")?),
+        output_dir: out.path().to_path_buf(),
+        ..SplitOptions::default()
+    };
+
+    let mut manifest = Vec::new();
+    let input = std::fs::File::open(test_patch_path("multi_hunk"))?;
+    let count = splitter::split(input, &options, "", &mut manifest)?;
+    assert_eq!(count, SplitCount { seen: 2, kept: 1 });
+
+    let outputs: Vec<_> = manifest.iter().map(|e| e.output.clone()).collect();
+    assert_eq!(outputs, [PathBuf::from("src-lib.rs.patch")]);
+    assert!(out.path().join("src-lib.rs.patch").is_file());
+
+    Ok(())
+}
+
+#[test]
+fn test_header_prefix() -> anyhow::Result<()> {
+    let out = tempfile::tempdir()?;
+    let options = SplitOptions {
+        output_dir: out.path().to_path_buf(),
+        header_prefix: Some("# Extracted by spatch".to_string()),
+        ..SplitOptions::default()
+    };
+
+    let input = std::fs::File::open(test_patch_path("multi"))?;
+    splitter::split(input, &options, "", &mut Vec::new())?;
+    let patch = std::fs::read_to_string(out.path().join("one.patch"))?;
+    assert!(patch.starts_with("# Extracted by spatch\ndiff --git a/one b/one\n"));
+
+    // Extracted files are left alone.
+    let options = SplitOptions {
+        filter: FilterType::OnlyNew(FileProcessing::ExtractFile(ExtractMode::NewSide)),
+        ..options
+    };
+    let input = std::fs::File::open(test_patch_path("format_patch"))?;
+    splitter::split(input, &options, "", &mut Vec::new())?;
+    assert_eq!(std::fs::read_to_string(out.path().join("two.txt"))?, "x\n");
+
+    Ok(())
+}
+
+/// Keeps every file in memory, as `(path, contents)`.
+#[derive(Default)]
+struct MemoryWriter {
+    files: Vec<(PathBuf, String)>,
+}
+
+impl PatchWriter for MemoryWriter {
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        self.files.push((path.to_path_buf(), String::new()));
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.files.last_mut().unwrap().1.push_str(line);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_split_with_writer() -> anyhow::Result<()> {
+    let options = SplitOptions {
+        split_by_hunk: true,
+        ..SplitOptions::default()
+    };
+
+    let mut writer = MemoryWriter::default();
+    let input = std::fs::File::open(test_patch_path("multi_hunk"))?;
+    let count = splitter::split_with(input, &options, "", &mut Vec::new(), &mut writer)?;
+    assert_eq!(count.kept, 2);
+
+    let paths: Vec<_> = writer
+        .files
+        .iter()
+        .map(|(p, _)| p.to_str().unwrap())
+        .collect();
+    assert_eq!(
+        paths,
+        ["src-lib.rs#1.patch", "src-lib.rs#2.patch", "README#1.patch"]
+    );
+    assert!(
+        writer.files[1]
+            .1
+            .starts_with("diff --git a/src/lib.rs b/src/lib.rs\n")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_combine_split_patches() -> anyhow::Result<()> {
+    // Header, body and binary payload of every patch, in name order.
+    let patches = |input: &[u8]| {
+        let mut patches: Vec<_> = DiffParser::new(input)
+            .into_owned_patches()
+            .map(|p| {
+                (
+                    p.header().to_string(),
+                    p.lines().to_vec(),
+                    p.binary_payload().map(str::to_string),
+                )
+            })
+            .collect();
+        patches.sort();
+        patches
+    };
+
+    for name in ["multi_hunk", "git_binary_patch", "crlf"] {
+        let out = tempfile::tempdir()?;
+        let options = SplitOptions {
+            output_dir: out.path().to_path_buf(),
+            ..SplitOptions::default()
+        };
+        let original = std::fs::read(test_patch_path(name))?;
+        splitter::split(original.as_slice(), &options, "", &mut Vec::new())?;
+
+        let mut files: Vec<_> = std::fs::read_dir(out.path())?
+            .map(|e| Ok(e?.path()))
+            .collect::<io::Result<_>>()?;
+        files.sort();
+        let mut combined = Vec::new();
+        let count = splitter::combine(&files, &mut combined)?;
+
+        assert_eq!(count, files.len());
+        assert_eq!(patches(&combined), patches(&original), "{}", name);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_numbered_chunks_are_refused() -> anyhow::Result<()> {
+    let options = SplitOptions {
+        numbered: true,
+        chunk_size: std::num::NonZeroUsize::new(2),
+        ..SplitOptions::default()
+    };
+
+    let mut writer = MemoryWriter::default();
+    let input = std::fs::File::open(test_patch_path("multi_hunk"))?;
+    let res = splitter::split_with(input, &options, "", &mut Vec::new(), &mut writer);
+    let err = res.expect_err("numbered chunks");
+    assert!(
+        err.to_string()
+            .contains("both numbered and written in chunks")
+    );
+    assert!(writer.files.is_empty());
+
+    Ok(())
+}
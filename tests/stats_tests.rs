@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+
+use spatch::diff_parser::DiffParser;
+use spatch::stats::{self, FileStat};
+
+fn test_patch_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("test_patches")
+        .join(format!("{}.patch", name))
+}
+
+#[test]
+fn test_diff_stat() -> anyhow::Result<()> {
+    let input = std::fs::read_to_string(test_patch_path("naming"))?
+        + &std::fs::read_to_string(test_patch_path("multiple_binaries"))?;
+    let stat = stats::diff_stat(DiffParser::from_str(&input))?;
+
+    assert_eq!(
+        (
+            stat.files_changed,
+            stat.insertions,
+            stat.deletions,
+            stat.binary_files
+        ),
+        (7, 2, 1, 3)
+    );
+    let paths: Vec<_> = stat
+        .patches
+        .iter()
+        .map(|m| m.new_path.as_deref().or(m.old_path.as_deref()))
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            Some("docs/new.md"),
+            Some("docs/old.md"),
+            Some("src/b.rs"),
+            Some("src/lib.rs"),
+            Some("icon.ico"),
+            Some("logo.svg"),
+            Some("archive.zip"),
+        ]
+    );
+    assert_eq!(
+        stat.files[2],
+        FileStat {
+            path: "src/a.rs => src/b.rs".into(),
+            added: 0,
+            removed: 0,
+            binary: false,
+        }
+    );
+    // The bodies were read as the parse went on, not dropped unread.
+    assert_eq!((stat.files[3].added, stat.files[3].removed), (1, 0));
+    assert!(stat.files[4..].iter().all(|f| f.binary));
+
+    Ok(())
+}